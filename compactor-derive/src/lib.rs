@@ -0,0 +1,154 @@
+//! `#[derive(Aggregate)]`.  Use this via the `derive` feature of the
+//! `compactor` crate, rather than depending on it directly.
+
+use proc_macro2::TokenStream;
+use quote::{quote, quote_spanned};
+use syn::{
+    Data, DeriveInput, Error, Fields, Index, LitStr, Path, Type, parse_macro_input,
+    spanned::Spanned,
+};
+
+/// Implements `Aggregate` by merging each field in turn.  By default a field
+/// is merged with its own `Aggregate` impl; use `#[aggregate(with = "...")]`
+/// to merge it some other way.  See the docs of the `compactor` crate.
+///
+/// If `compactor` has been re-exported under another name, specify the path
+/// to it with `#[aggregate(crate = "...")]` on the struct.
+#[proc_macro_derive(Aggregate, attributes(aggregate))]
+pub fn derive_aggregate(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// How to merge a field
+enum Strategy {
+    /// Its own `Aggregate` impl
+    Aggregate,
+    /// One of the functions in `compactor::aggregate::merge`
+    Builtin(&'static str),
+    /// A user-supplied `fn(&mut T, T)`
+    Custom(Path),
+}
+
+const BUILTINS: &[&str] = &["sum", "min", "max", "first", "last"];
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream> {
+    let Data::Struct(data) = &input.data else {
+        return Err(Error::new(
+            input.span(),
+            "Aggregate can only be derived for structs",
+        ));
+    };
+    let krate = crate_path(&input)?;
+    let mut merges = vec![];
+    let mut bounds = vec![];
+    let fields = match &data.fields {
+        Fields::Named(fields) => fields.named.iter().collect(),
+        Fields::Unnamed(fields) => fields.unnamed.iter().collect(),
+        Fields::Unit => vec![],
+    };
+    for (i, field) in fields.into_iter().enumerate() {
+        let member = match &field.ident {
+            Some(ident) => quote!(#ident),
+            None => {
+                let i = Index::from(i);
+                quote!(#i)
+            }
+        };
+        let ty = &field.ty;
+        let span = field.ty.span();
+        match strategy(field)? {
+            Strategy::Aggregate => {
+                bounds.push(quote_spanned!(span=> #ty: #krate::Aggregate));
+                merges.push(quote_spanned!(span=>
+                    #krate::Aggregate::merge(&mut self.#member, other.#member);
+                ));
+            }
+            Strategy::Builtin(name) => {
+                bounds.extend(builtin_bound(name, ty));
+                let f = syn::Ident::new(name, span);
+                merges.push(quote_spanned!(span=>
+                    #krate::aggregate::merge::#f(&mut self.#member, other.#member);
+                ));
+            }
+            Strategy::Custom(path) => {
+                merges.push(quote_spanned!(span=>
+                    #path(&mut self.#member, other.#member);
+                ));
+            }
+        }
+    }
+
+    let name = &input.ident;
+    let mut generics = input.generics.clone();
+    let where_clause = generics.make_where_clause();
+    for bound in bounds {
+        where_clause.predicates.push(syn::parse2(bound)?);
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics #krate::Aggregate for #name #ty_generics #where_clause {
+            fn merge(&mut self, other: Self) {
+                #(#merges)*
+            }
+        }
+    })
+}
+
+fn builtin_bound(name: &str, ty: &Type) -> Option<TokenStream> {
+    match name {
+        "sum" => Some(quote!(#ty: ::core::ops::AddAssign)),
+        "min" | "max" => Some(quote!(#ty: ::core::cmp::PartialOrd)),
+        _ => None,
+    }
+}
+
+fn crate_path(input: &DeriveInput) -> syn::Result<Path> {
+    let mut ret = syn::parse_quote!(::compactor);
+    for attr in &input.attrs {
+        if !attr.path().is_ident("aggregate") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("crate") {
+                let s: LitStr = meta.value()?.parse()?;
+                ret = s.parse()?;
+                Ok(())
+            } else {
+                Err(meta.error("unknown aggregate attribute"))
+            }
+        })?;
+    }
+    Ok(ret)
+}
+
+fn strategy(field: &syn::Field) -> syn::Result<Strategy> {
+    let mut ret = Strategy::Aggregate;
+    for attr in &field.attrs {
+        if !attr.path().is_ident("aggregate") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            let strategy = if meta.path.is_ident("with") {
+                let s: LitStr = meta.value()?.parse()?;
+                match BUILTINS.iter().find(|x| s.value() == **x) {
+                    Some(name) => Strategy::Builtin(name),
+                    None => Strategy::Custom(s.parse()?),
+                }
+            } else if let Some(name) = BUILTINS.iter().find(|x| meta.path.is_ident(x)) {
+                // `#[aggregate(min)]` is short for `#[aggregate(with = "min")]`
+                Strategy::Builtin(name)
+            } else {
+                return Err(meta.error("unknown aggregate attribute"));
+            };
+            if !matches!(ret, Strategy::Aggregate) {
+                return Err(meta.error("only one merge strategy can be given"));
+            }
+            ret = strategy;
+            Ok(())
+        })?;
+    }
+    Ok(ret)
+}
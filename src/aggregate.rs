@@ -1,9 +1,95 @@
+use crate::codec::Codec;
+use std::{
+    collections::{BTreeMap, HashMap, btree_map, hash_map},
+    hash::{BuildHasher, Hash},
+    io,
+    ops::AddAssign,
+};
+
+mod counter;
+mod histogram;
+mod hll;
+pub mod laws;
+mod reservoir;
+mod rng;
+mod sketch;
+mod time_weighted;
+mod top_k;
+pub use counter::Counter;
+pub use histogram::{Buckets, Histogram};
+pub use hll::HyperLogLog;
+pub use reservoir::Reservoir;
+pub use sketch::QuantileSketch;
+pub use time_weighted::{FromSample, Interpolation, Linear, Step, TimeWeightedMean};
+pub use top_k::TopK;
+
 /// aka. `Semigroup` in Haskell-speak
+///
+/// With the `derive` feature, this can be derived for structs.  Each field is
+/// merged in turn, using its own `Aggregate` impl by default.  Fields of
+/// plain types can use one of the functions in [`merge`] instead:
+///
+/// ```
+/// # #[cfg(feature = "derive")] {
+/// use compactor::Aggregate;
+///
+/// #[derive(Aggregate)]
+/// struct Stats<T> {
+///     #[aggregate(with = "sum")]
+///     count: u64,
+///     #[aggregate(min)]
+///     lo: T,
+///     #[aggregate(max)]
+///     hi: T,
+///     samples: Vec<T>,
+/// }
+/// # }
+/// ```
+///
+/// `with` can also be the path to any function with the same signature as
+/// the ones in [`merge`].  Tuple structs are supported too.
+///
+/// Merging must be associative: compaction merges values in whatever groups
+/// the policy dictates, and the result shouldn't depend on that.  You can
+/// check this with [`laws::assert_associative`].
 pub trait Aggregate: Sized {
-    /// Does **not** need to be commutative
+    /// Does **not** need to be commutative (but see [`CommutativeAggregate`])
     fn merge(&mut self, other: Self);
 }
 
+/// An [`Aggregate`] where the order of merging doesn't matter:
+/// `a.merge(b)` gives the same result as `b.merge(a)` (up to rounding
+/// error, for floating-point types)
+///
+/// This is required by APIs which can't guarantee that values are merged in
+/// the order they were pushed, like
+/// [`accept_late`](crate::CompactorBuilder::accept_late).  You can check that
+/// your impl is really commutative with [`laws::assert_commutative`].
+pub trait CommutativeAggregate: Aggregate {}
+
+/// A [`CommutativeAggregate`] which can be taken back out again
+///
+/// `unmerge` is the inverse of `merge`: after `a.merge(b.clone())` then
+/// `a.unmerge(b)`, `a` is back where it started (up to rounding error, for
+/// floating-point types).  If `b` can't have been merged into `a` (say, it
+/// would make a count negative), `unmerge` returns an error and leaves `a`
+/// alone.  This is what lets
+/// [`Compactor::retract`](crate::Compactor::retract) remove a value from a
+/// bucket, even after it's been compacted.  You can check your impl with
+/// [`laws::assert_invertible`].
+///
+/// aka. an abelian group
+pub trait InvertibleAggregate: CommutativeAggregate {
+    /// Only needs to give the right answer if `other` was merged into `self`
+    /// at some point
+    fn unmerge(&mut self, other: Self) -> Result<(), Underflow>;
+}
+
+/// Taking a value out of an aggregate would leave less than nothing, so it
+/// can't have been in there
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Underflow;
+
 impl<T: Aggregate> Aggregate for Option<T> {
     fn merge(&mut self, other: Self) {
         if let Some(this) = self {
@@ -15,6 +101,7 @@ impl<T: Aggregate> Aggregate for Option<T> {
         }
     }
 }
+impl<T: CommutativeAggregate> CommutativeAggregate for Option<T> {}
 
 impl<T> Aggregate for Vec<T> {
     fn merge(&mut self, mut other: Self) {
@@ -22,6 +109,103 @@ impl<T> Aggregate for Vec<T> {
     }
 }
 
+impl<T: Aggregate, const N: usize> Aggregate for [T; N] {
+    fn merge(&mut self, other: Self) {
+        for (x, y) in self.iter_mut().zip(other) {
+            x.merge(y);
+        }
+    }
+}
+impl<T: CommutativeAggregate, const N: usize> CommutativeAggregate for [T; N] {}
+impl<T: InvertibleAggregate + Clone, const N: usize> InvertibleAggregate for [T; N] {
+    fn unmerge(&mut self, other: Self) -> Result<(), Underflow> {
+        let old = self.clone();
+        for (x, y) in self.iter_mut().zip(other) {
+            if let Err(e) = x.unmerge(y) {
+                *self = old;
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Values with the same key are merged
+impl<K: Eq + Hash, V: Aggregate, S: BuildHasher> Aggregate for HashMap<K, V, S> {
+    fn merge(&mut self, other: Self) {
+        for (k, v) in other {
+            match self.entry(k) {
+                hash_map::Entry::Occupied(mut e) => e.get_mut().merge(v),
+                hash_map::Entry::Vacant(e) => {
+                    e.insert(v);
+                }
+            }
+        }
+    }
+}
+impl<K: Eq + Hash, V: CommutativeAggregate, S: BuildHasher> CommutativeAggregate
+    for HashMap<K, V, S>
+{
+}
+
+/// Values with the same key are merged.  (std has an unstable `merge` method
+/// on `BTreeMap` too, so you may need to call this as `Aggregate::merge`.)
+impl<K: Ord, V: Aggregate> Aggregate for BTreeMap<K, V> {
+    fn merge(&mut self, other: Self) {
+        for (k, v) in other {
+            match self.entry(k) {
+                btree_map::Entry::Occupied(mut e) => e.get_mut().merge(v),
+                btree_map::Entry::Vacant(e) => {
+                    e.insert(v);
+                }
+            }
+        }
+    }
+}
+impl<K: Ord, V: CommutativeAggregate> CommutativeAggregate for BTreeMap<K, V> {}
+
+/// Each element is merged with the corresponding one
+macro_rules! impl_aggregate_tuple {
+    ($($t:ident $i:tt),*) => {
+        impl<$($t: Aggregate),*> Aggregate for ($($t,)*) {
+            #[allow(unused_variables)]
+            fn merge(&mut self, other: Self) {
+                $(self.$i.merge(other.$i);)*
+            }
+        }
+        impl<$($t: CommutativeAggregate),*> CommutativeAggregate for ($($t,)*) {}
+        impl<$($t: InvertibleAggregate + Clone),*> InvertibleAggregate for ($($t,)*) {
+            #[allow(unused_variables)]
+            fn unmerge(&mut self, other: Self) -> Result<(), Underflow> {
+                let old = self.clone();
+                $(
+                    if let Err(e) = self.$i.unmerge(other.$i) {
+                        *self = old;
+                        return Err(e);
+                    }
+                )*
+                Ok(())
+            }
+        }
+    };
+}
+
+impl_aggregate_tuple!();
+impl_aggregate_tuple!(A 0);
+impl_aggregate_tuple!(A 0, B 1);
+impl_aggregate_tuple!(A 0, B 1, C 2);
+impl_aggregate_tuple!(A 0, B 1, C 2, D 3);
+impl_aggregate_tuple!(A 0, B 1, C 2, D 3, E 4);
+impl_aggregate_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+impl_aggregate_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+impl_aggregate_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+impl_aggregate_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8);
+impl_aggregate_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9);
+impl_aggregate_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9, K 10);
+impl_aggregate_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9, K 10, L 11);
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Min<T>(pub T);
 impl<T: PartialOrd> Aggregate for Min<T> {
     fn merge(&mut self, other: Self) {
@@ -30,7 +214,10 @@ impl<T: PartialOrd> Aggregate for Min<T> {
         }
     }
 }
+impl<T: PartialOrd> CommutativeAggregate for Min<T> {}
 
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Max<T>(pub T);
 impl<T: PartialOrd> Aggregate for Max<T> {
     fn merge(&mut self, other: Self) {
@@ -39,7 +226,10 @@ impl<T: PartialOrd> Aggregate for Max<T> {
         }
     }
 }
+impl<T: PartialOrd> CommutativeAggregate for Max<T> {}
 
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct First<T>(pub T);
 impl<T> Aggregate for First<T> {
     fn merge(&mut self, _: Self) {
@@ -47,6 +237,8 @@ impl<T> Aggregate for First<T> {
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Last<T>(pub T);
 impl<T> Aggregate for Last<T> {
     fn merge(&mut self, other: Self) {
@@ -54,6 +246,8 @@ impl<T> Aggregate for Last<T> {
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Candlestick<T> {
     pub first: First<T>,
     pub last: Last<T>,
@@ -80,3 +274,593 @@ impl<T: PartialOrd> Aggregate for Candlestick<T> {
         self.max.merge(other.max);
     }
 }
+
+impl<T: Codec> Codec for Candlestick<T> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.first.encode(buf);
+        self.last.encode(buf);
+        self.min.encode(buf);
+        self.max.encode(buf);
+    }
+    fn decode(buf: &mut &[u8]) -> io::Result<Self> {
+        Ok(Candlestick {
+            first: Codec::decode(buf)?,
+            last: Codec::decode(buf)?,
+            min: Codec::decode(buf)?,
+            max: Codec::decode(buf)?,
+        })
+    }
+}
+
+/// Adds up the values
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Sum<T>(pub T);
+impl<T: AddAssign> Aggregate for Sum<T> {
+    fn merge(&mut self, other: Self) {
+        self.0 += other.0;
+    }
+}
+/// Assuming `+=` is commutative, which it is for all the usual types
+impl<T: AddAssign> CommutativeAggregate for Sum<T> {}
+impl<T: Numeric> InvertibleAggregate for Sum<T> {
+    fn unmerge(&mut self, other: Self) -> Result<(), Underflow> {
+        self.0 = self.0.checked_sub(other.0).ok_or(Underflow)?;
+        Ok(())
+    }
+}
+
+macro_rules! impl_codec_newtype {
+    ($($t:ident),*) => {$(
+        impl<T: Codec> Codec for $t<T> {
+            fn encode(&self, buf: &mut Vec<u8>) {
+                self.0.encode(buf);
+            }
+            fn decode(buf: &mut &[u8]) -> io::Result<Self> {
+                Ok($t(T::decode(buf)?))
+            }
+        }
+    )*};
+}
+
+impl_codec_newtype!(Min, Max, First, Last, Sum);
+
+/// Counts the values.  Push `Count(1)` for each one.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Count(pub u64);
+impl Aggregate for Count {
+    fn merge(&mut self, other: Self) {
+        self.0 += other.0;
+    }
+}
+impl CommutativeAggregate for Count {}
+impl InvertibleAggregate for Count {
+    fn unmerge(&mut self, other: Self) -> Result<(), Underflow> {
+        self.0 = self.0.checked_sub(other.0).ok_or(Underflow)?;
+        Ok(())
+    }
+}
+
+impl Codec for Count {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.0.encode(buf);
+    }
+    fn decode(buf: &mut &[u8]) -> io::Result<Self> {
+        Ok(Count(u64::decode(buf)?))
+    }
+}
+
+/// The arithmetic mean of the values
+///
+/// Averaging the averages would give the wrong answer once buckets of
+/// different sizes are merged, so this keeps the sum and the count, and only
+/// divides when you ask for the mean.
+///
+/// ```
+/// # use compactor::{Aggregate, aggregate::Mean};
+/// let mut x = Mean::from(1);
+/// x.merge(Mean::from(2));
+/// x.merge(Mean::from(6));
+/// assert_eq!(x.mean(), 3.0);
+/// ```
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mean<T> {
+    pub sum: T,
+    pub count: u64,
+}
+
+impl<T> From<T> for Mean<T> {
+    fn from(x: T) -> Self {
+        Mean { sum: x, count: 1 }
+    }
+}
+
+impl<T: AddAssign> Aggregate for Mean<T> {
+    fn merge(&mut self, other: Self) {
+        self.sum += other.sum;
+        self.count += other.count;
+    }
+}
+impl<T: AddAssign> CommutativeAggregate for Mean<T> {}
+impl<T: Numeric> InvertibleAggregate for Mean<T> {
+    fn unmerge(&mut self, other: Self) -> Result<(), Underflow> {
+        let sum = self.sum.checked_sub(other.sum).ok_or(Underflow)?;
+        let count = self.count.checked_sub(other.count).ok_or(Underflow)?;
+        *self = Mean { sum, count };
+        Ok(())
+    }
+}
+
+impl<T: Codec> Codec for Mean<T> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.sum.encode(buf);
+        self.count.encode(buf);
+    }
+    fn decode(buf: &mut &[u8]) -> io::Result<Self> {
+        Ok(Mean {
+            sum: T::decode(buf)?,
+            count: u64::decode(buf)?,
+        })
+    }
+}
+
+impl<T: Numeric> Mean<T> {
+    /// NaN if there are no values
+    pub fn mean(&self) -> f64 {
+        self.sum.to_f64() / self.count as f64
+    }
+}
+
+/// The count, mean, variance, and skewness of the values
+///
+/// Merging uses the pairwise update formulas of Chan et al. (extended to the
+/// third moment by Pébay), which are numerically stable and give the same
+/// answer however the values are grouped into buckets.
+///
+/// ```
+/// # use compactor::{Aggregate, aggregate::Moments};
+/// let mut x = Moments::from(2);
+/// for y in [4, 4, 4, 5, 5, 7, 9] {
+///     x.merge(Moments::from(y));
+/// }
+/// assert_eq!(x.mean(), 5.0);
+/// assert_eq!(x.stddev(), 2.0);
+/// ```
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Moments {
+    count: u64,
+    mean: f64,
+    /// Sum of squared differences from the mean
+    m2: f64,
+    /// Sum of cubed differences from the mean
+    m3: f64,
+}
+
+impl<T: Numeric> From<T> for Moments {
+    fn from(x: T) -> Self {
+        Moments {
+            count: 1,
+            mean: x.to_f64(),
+            m2: 0.0,
+            m3: 0.0,
+        }
+    }
+}
+
+impl Aggregate for Moments {
+    fn merge(&mut self, other: Self) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 {
+            *self = other;
+            return;
+        }
+        let (na, nb) = (self.count as f64, other.count as f64);
+        let n = na + nb;
+        let delta = other.mean - self.mean;
+        self.m3 += other.m3
+            + delta.powi(3) * na * nb * (na - nb) / (n * n)
+            + 3.0 * delta * (na * other.m2 - nb * self.m2) / n;
+        self.m2 += other.m2 + delta * delta * na * nb / n;
+        self.mean += delta * nb / n;
+        self.count += other.count;
+    }
+}
+impl CommutativeAggregate for Moments {}
+impl InvertibleAggregate for Moments {
+    /// The merge formulas, solved for `self`'s old value
+    fn unmerge(&mut self, other: Self) -> Result<(), Underflow> {
+        if other.count == 0 {
+            return Ok(());
+        }
+        let count = self.count.checked_sub(other.count).ok_or(Underflow)?;
+        if count == 0 {
+            *self = Moments::default();
+            return Ok(());
+        }
+        let (na, nb) = (count as f64, other.count as f64);
+        let n = na + nb;
+        let mean = (self.mean * n - other.mean * nb) / na;
+        let delta = other.mean - mean;
+        let m2 = self.m2 - other.m2 - delta * delta * na * nb / n;
+        self.m3 -= other.m3
+            + delta.powi(3) * na * nb * (na - nb) / (n * n)
+            + 3.0 * delta * (na * other.m2 - nb * m2) / n;
+        self.m2 = m2;
+        self.mean = mean;
+        self.count = count;
+        Ok(())
+    }
+}
+
+impl Codec for Moments {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.count.encode(buf);
+        self.mean.encode(buf);
+        self.m2.encode(buf);
+        self.m3.encode(buf);
+    }
+    fn decode(buf: &mut &[u8]) -> io::Result<Self> {
+        Ok(Moments {
+            count: u64::decode(buf)?,
+            mean: f64::decode(buf)?,
+            m2: f64::decode(buf)?,
+            m3: f64::decode(buf)?,
+        })
+    }
+}
+
+impl Moments {
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// NaN if there are no values
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 { f64::NAN } else { self.mean }
+    }
+
+    /// The population variance.  NaN if there are no values.
+    pub fn variance(&self) -> f64 {
+        self.m2 / self.count as f64
+    }
+
+    /// The sample variance (with Bessel's correction).  NaN if there are
+    /// fewer than two values.
+    pub fn sample_variance(&self) -> f64 {
+        if self.count < 2 {
+            return f64::NAN;
+        }
+        self.m2 / (self.count - 1) as f64
+    }
+
+    /// The population standard deviation
+    pub fn stddev(&self) -> f64 {
+        self.variance().sqrt()
+    }
+
+    /// The sample standard deviation
+    pub fn sample_stddev(&self) -> f64 {
+        self.sample_variance().sqrt()
+    }
+
+    /// The population skewness.  NaN if all the values are the same.
+    pub fn skewness(&self) -> f64 {
+        (self.count as f64).sqrt() * self.m3 / self.m2.powf(1.5)
+    }
+}
+
+/// The primitive integer and float types
+pub trait Numeric: Copy + AddAssign + PartialOrd {
+    /// May lose precision
+    fn to_f64(self) -> f64;
+
+    /// `None` on overflow.  Floats never overflow (they go to infinity).
+    fn checked_sub(self, other: Self) -> Option<Self>;
+}
+
+macro_rules! impl_numeric {
+    ($($t:ty),*) => {$(
+        impl Numeric for $t {
+            fn to_f64(self) -> f64 {
+                self as f64
+            }
+
+            fn checked_sub(self, other: Self) -> Option<Self> {
+                <$t>::checked_sub(self, other)
+            }
+        }
+    )*};
+}
+
+impl_numeric!(
+    u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize
+);
+
+macro_rules! impl_numeric_float {
+    ($($t:ty),*) => {$(
+        impl Numeric for $t {
+            fn to_f64(self) -> f64 {
+                self as f64
+            }
+
+            fn checked_sub(self, other: Self) -> Option<Self> {
+                Some(self - other)
+            }
+        }
+    )*};
+}
+
+impl_numeric_float!(f32, f64);
+
+/// Ways of merging values which don't implement [`Aggregate`] themselves,
+/// for use with `#[derive(Aggregate)]`
+pub mod merge {
+    use super::AddAssign;
+
+    pub fn sum<T: AddAssign>(this: &mut T, other: T) {
+        *this += other;
+    }
+
+    pub fn min<T: PartialOrd>(this: &mut T, other: T) {
+        if *this > other {
+            *this = other;
+        }
+    }
+
+    pub fn max<T: PartialOrd>(this: &mut T, other: T) {
+        if *this < other {
+            *this = other;
+        }
+    }
+
+    pub fn first<T>(_: &mut T, _: T) {
+        // no-op!
+    }
+
+    pub fn last<T>(this: &mut T, other: T) {
+        *this = other;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mean() {
+        // Merge buckets of different sizes, like compaction does
+        let mut small = Mean::from(10_u32);
+        let mut big = Mean::from(1);
+        for _ in 0..3 {
+            big.merge(Mean::from(1));
+        }
+        small.merge(big);
+        assert_eq!(small, Mean { sum: 14, count: 5 });
+        assert_eq!(small.mean(), 2.8);
+        assert!(Mean::<f32>::default().mean().is_nan());
+
+        let mut x = Sum(1.5);
+        x.merge(Sum(2.0));
+        assert_eq!(x, Sum(3.5));
+        let mut x = Count(1);
+        x.merge(Count(2));
+        assert_eq!(x, Count(3));
+    }
+
+    #[test]
+    fn test_composite() {
+        let mut x = (Min(1.5), Max(1.5), Count(1));
+        x.merge((Min(0.5), Max(0.5), Count(1)));
+        x.merge((Min(3.0), Max(3.0), Count(1)));
+        assert_eq!(x, (Min(0.5), Max(3.0), Count(3)));
+
+        let mut x = [Sum(1), Sum(2), Sum(3)];
+        x.merge([Sum(10), Sum(20), Sum(30)]);
+        assert_eq!(x, [Sum(11), Sum(22), Sum(33)]);
+
+        // Per-label breakdowns
+        let mut x = HashMap::from([("GET", Count(3)), ("PUT", Count(1))]);
+        x.merge(HashMap::from([("GET", Count(2)), ("POST", Count(5))]));
+        assert_eq!(
+            x,
+            HashMap::from([("GET", Count(5)), ("PUT", Count(1)), ("POST", Count(5))])
+        );
+        let mut x = BTreeMap::from([(200, (Count(1), Sum(0.5)))]);
+        Aggregate::merge(
+            &mut x,
+            BTreeMap::from([(200, (Count(1), Sum(0.25))), (500, (Count(1), Sum(3.0)))]),
+        );
+        assert_eq!(
+            x.into_iter().collect::<Vec<_>>(),
+            vec![(200, (Count(2), Sum(0.75))), (500, (Count(1), Sum(3.0)))]
+        );
+
+        let mut x = ();
+        x.merge(());
+    }
+
+    #[test]
+    fn test_underflow() {
+        let mut x = Count(3);
+        assert_eq!(x.unmerge(Count(4)), Err(Underflow));
+        assert_eq!(x, Count(3));
+        let mut x = Sum(-3i8);
+        assert_eq!(x.unmerge(Sum(i8::MAX)), Err(Underflow));
+        assert_eq!(x.unmerge(Sum(-5)), Ok(()));
+        assert_eq!(x, Sum(2));
+        // All or nothing
+        let mut x = (Sum(5u32), [Count(1), Count(2)]);
+        assert_eq!(x.unmerge((Sum(1), [Count(1), Count(3)])), Err(Underflow));
+        assert_eq!(x, (Sum(5), [Count(1), Count(2)]));
+        let mut x = Mean::from(1.5);
+        assert_eq!(x.unmerge(Mean { sum: 0.5, count: 2 }), Err(Underflow));
+        let mut x = Moments::from(1);
+        assert_eq!(x.unmerge(Moments::from(1)), Ok(()));
+        assert_eq!(x.unmerge(Moments::from(1)), Err(Underflow));
+    }
+
+    /// Computes the stats the slow way
+    fn naive(xs: &[f64]) -> (f64, f64, f64) {
+        let n = xs.len() as f64;
+        let mean = xs.iter().sum::<f64>() / n;
+        let m2 = xs.iter().map(|x| (x - mean).powi(2)).sum::<f64>();
+        let m3 = xs.iter().map(|x| (x - mean).powi(3)).sum::<f64>();
+        (mean, m2 / n, n.sqrt() * m3 / m2.powf(1.5))
+    }
+
+    fn assert_close(x: f64, y: f64) {
+        assert!((x - y).abs() <= 1e-9 * y.abs().max(1.0), "{x} != {y}");
+    }
+
+    #[test]
+    fn test_moments() {
+        let xs = [3.0, 1.0, 4.0, 1.0, 5.0, 9.0, 2.0, 6.0, 5.0, 3.0, 5.0];
+        let (mean, var, skew) = naive(&xs);
+        // Merge in various groupings
+        for split in 0..=xs.len() {
+            let moments = |xs: &[f64]| {
+                let mut acc = Moments::default();
+                for x in xs {
+                    acc.merge(Moments::from(*x));
+                }
+                acc
+            };
+            let mut acc = moments(&xs[..split]);
+            acc.merge(moments(&xs[split..]));
+            assert_eq!(acc.count(), xs.len() as u64);
+            assert_close(acc.mean(), mean);
+            assert_close(acc.variance(), var);
+            assert_close(acc.skewness(), skew);
+        }
+
+        // Stable with a large offset
+        let mut acc = Moments::default();
+        for x in [1e9 + 4.0, 1e9 + 7.0, 1e9 + 13.0, 1e9 + 16.0] {
+            acc.merge(Moments::from(x));
+        }
+        assert_eq!(acc.variance(), 22.5);
+        assert_eq!(acc.sample_variance(), 30.0);
+
+        assert!(Moments::default().mean().is_nan());
+        assert!(Moments::from(1).sample_variance().is_nan());
+    }
+
+    #[test]
+    fn test_moments_through_tiers() {
+        use crate::{Compactor, Date, Resolution, Time};
+        fn compactor<T>() -> Compactor<T> {
+            Compactor::new()
+                .keep_for_days(1, Resolution::Minute)
+                .keep_for_days(2, Resolution::FifteenMinute)
+                .keep_for_days(4, Resolution::Hour)
+                .keep_for_days(10, Resolution::Day)
+                .build()
+                .unwrap()
+        }
+        let mut moments = compactor();
+        let mut raw = compactor();
+        for d in 1..=6 {
+            let date = Date {
+                year: 2024,
+                month: 3,
+                day: d,
+            };
+            for i in 0..24 * 60 {
+                let time = Time::from_hour((i / 60) as u8).with_minute((i % 60) as u8);
+                // Some skewed pseudo-random values
+                let x = ((i * 7919 + d as u32 * 104729) % 1000) as f64;
+                let x = x * x / 1000.0;
+                moments.push(date, time, Moments::from(x)).unwrap();
+                raw.push(date, time, vec![x]).unwrap();
+            }
+        }
+        assert_eq!(moments.len(), raw.len());
+        assert!(moments.per_resolution().count() > 1);
+        for ((d1, t1, m), (d2, t2, xs)) in moments.iter().zip(raw.iter()) {
+            assert_eq!((d1, t1), (d2, t2));
+            let (mean, var, _) = naive(xs);
+            assert_eq!(m.count(), xs.len() as u64);
+            assert_close(m.mean(), mean);
+            assert_close(m.variance(), var);
+            if xs.len() > 2 {
+                assert_close(m.skewness(), naive(xs).2);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "derive"))]
+mod derive_tests {
+    use crate::Aggregate;
+
+    #[derive(Aggregate, PartialEq, Debug)]
+    #[aggregate(crate = "crate")]
+    struct Stats<T> {
+        #[aggregate(with = "sum")]
+        count: u32,
+        #[aggregate(with = "min")]
+        min: T,
+        #[aggregate(max)]
+        max: T,
+        #[aggregate(with = "first")]
+        first: T,
+        #[aggregate(with = "last")]
+        last: T,
+        all: Vec<T>,
+    }
+
+    impl<T: Copy> From<T> for Stats<T> {
+        fn from(x: T) -> Self {
+            Stats {
+                count: 1,
+                min: x,
+                max: x,
+                first: x,
+                last: x,
+                all: vec![x],
+            }
+        }
+    }
+
+    fn concat(this: &mut String, other: String) {
+        this.push_str(&other);
+    }
+
+    #[derive(Aggregate, PartialEq, Debug)]
+    #[aggregate(crate = "crate")]
+    struct Tuple(Option<Vec<u8>>, #[aggregate(with = "concat")] String);
+
+    #[derive(Aggregate)]
+    #[aggregate(crate = "crate")]
+    struct Unit;
+
+    #[test]
+    fn test_derive() {
+        let mut x = Stats::from(3.5);
+        x.merge(Stats::from(1.0));
+        x.merge(Stats::from(2.0));
+        assert_eq!(
+            x,
+            Stats {
+                count: 3,
+                min: 1.0,
+                max: 3.5,
+                first: 3.5,
+                last: 2.0,
+                all: vec![3.5, 1.0, 2.0],
+            }
+        );
+
+        let mut x = Tuple(None, "a".into());
+        x.merge(Tuple(Some(vec![1]), "b".into()));
+        x.merge(Tuple(Some(vec![2]), "c".into()));
+        assert_eq!(x, Tuple(Some(vec![1, 2]), "abc".into()));
+
+        Unit.merge(Unit);
+    }
+}
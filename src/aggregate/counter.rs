@@ -0,0 +1,154 @@
+use super::Aggregate;
+use crate::{
+    Resolution,
+    codec::{Codec, decode_varint, encode_varint, invalid_data},
+};
+use std::io;
+
+/// The increase in a monotonic counter, allowing for resets
+///
+/// Push the counter's raw value (eg. total bytes sent).  If the value goes
+/// down, the counter is assumed to have been reset to zero (say, because the
+/// process restarted), and the increase carries on from there.
+///
+/// A bucket only sees the samples inside it, so its increase runs from its
+/// first sample to its last.  When buckets are merged, the increase between
+/// them is filled in, so a coarse bucket's increase is the same as if all its
+/// samples had been in one bucket.
+///
+/// Buckets must be merged in time order (which is what a compactor does).
+///
+/// ```
+/// # use compactor::{Compactor, Date, Resolution, Time, aggregate::Counter};
+/// let mut bytes_sent = Compactor::<Counter>::new()
+///     .keep_for_days(1, Resolution::Hour)
+///     .build()
+///     .unwrap();
+/// let date = Date { year: 2024, month: 3, day: 1 };
+/// let minute = |m| Time::from_hour(9).with_minute(m);
+/// bytes_sent.push(date, minute(0), Counter::from(1000)).unwrap();
+/// bytes_sent.push(date, minute(30), Counter::from(5000)).unwrap();
+/// // The process restarted
+/// bytes_sent.push(date, minute(40), Counter::from(600)).unwrap();
+/// bytes_sent.push(date, minute(59), Counter::from(3200)).unwrap();
+/// let (_, time, x) = bytes_sent.iter().next().unwrap();
+/// assert_eq!(x.increase(), 4000 + 3200);
+/// assert_eq!(x.resets(), 1);
+/// assert_eq!(x.rate(time.resolution()), 2.0);
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Counter {
+    first: u64,
+    last: u64,
+    increase: u64,
+    resets: u32,
+}
+
+impl From<u64> for Counter {
+    fn from(x: u64) -> Self {
+        Counter {
+            first: x,
+            last: x,
+            increase: 0,
+            resets: 0,
+        }
+    }
+}
+
+impl Counter {
+    /// The first value the counter had
+    pub fn first(&self) -> u64 {
+        self.first
+    }
+
+    /// The last value the counter had
+    pub fn last(&self) -> u64 {
+        self.last
+    }
+
+    /// The total increase, including from before any resets
+    pub fn increase(&self) -> u64 {
+        self.increase
+    }
+
+    /// The number of times the counter went down
+    pub fn resets(&self) -> u32 {
+        self.resets
+    }
+
+    /// The increase per second, for a bucket of the given resolution.  Get
+    /// the resolution from the bucket's [`Time`](crate::Time).
+    ///
+    /// For months, quarters, and years, this uses the average length.
+    pub fn rate(&self, res: Resolution) -> f64 {
+        self.increase as f64 / res.width().as_secs_f64()
+    }
+}
+
+impl Aggregate for Counter {
+    fn merge(&mut self, other: Self) {
+        if other.first >= self.last {
+            self.increase += other.first - self.last;
+        } else {
+            // It was reset to zero, and has counted up from there
+            self.increase += other.first;
+            self.resets += 1;
+        }
+        self.increase += other.increase;
+        self.resets += other.resets;
+        self.last = other.last;
+    }
+}
+
+impl Codec for Counter {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        encode_varint(self.first, buf);
+        encode_varint(self.last, buf);
+        encode_varint(self.increase, buf);
+        encode_varint(self.resets as u64, buf);
+    }
+    fn decode(buf: &mut &[u8]) -> io::Result<Self> {
+        Ok(Counter {
+            first: decode_varint(buf)?,
+            last: decode_varint(buf)?,
+            increase: decode_varint(buf)?,
+            resets: decode_varint(buf)?
+                .try_into()
+                .map_err(|_| invalid_data("too many resets"))?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregate::laws;
+
+    fn fold(xs: &[u64]) -> Counter {
+        let mut acc = Counter::from(xs[0]);
+        for x in &xs[1..] {
+            acc.merge(Counter::from(*x));
+        }
+        acc
+    }
+
+    #[test]
+    fn test_counter() {
+        let x = fold(&[5, 10, 10, 25]);
+        assert_eq!((x.first(), x.last()), (5, 25));
+        assert_eq!((x.increase(), x.resets()), (20, 0));
+        let x = fold(&[5, 10, 3, 7, 0, 2]);
+        assert_eq!((x.increase(), x.resets()), (5 + 7 + 2, 2));
+        assert_eq!(x.rate(Resolution::Minute), 14.0 / 60.0);
+
+        let xs = [100, 120, 4, 50, 50, 1, 9, 300, 2];
+        let all = fold(&xs);
+        for split in 1..xs.len() {
+            let mut acc = fold(&xs[..split]);
+            acc.merge(fold(&xs[split..]));
+            assert_eq!(acc, all);
+        }
+        laws::assert_associative(|x| Counter::from(x % 100));
+    }
+}
@@ -0,0 +1,312 @@
+use super::{Aggregate, CommutativeAggregate};
+use crate::codec::{Codec, decode_varint, encode_varint};
+use std::{fmt, io, marker::PhantomData};
+
+/// The upper bounds of the buckets of a [`Histogram`]
+///
+/// Like Prometheus, a value goes in the first bucket whose bound is at least
+/// as big as it.  There's an extra bucket at the end for values bigger than
+/// all the bounds.
+///
+/// The bounds are part of the histogram's type, so histograms with different
+/// buckets can't be merged by mistake, and they don't need to be stored with
+/// every entry.
+///
+/// ```
+/// # use compactor::aggregate::Buckets;
+/// /// Request latency, in seconds
+/// struct Latency;
+/// impl Buckets for Latency {
+///     const BOUNDS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0];
+/// }
+/// ```
+pub trait Buckets {
+    /// These must be finite and strictly increasing, and there must be at
+    /// least one.  This is checked at compile time:
+    ///
+    /// ```compile_fail
+    /// # use compactor::aggregate::{Buckets, Histogram};
+    /// struct Backwards;
+    /// impl Buckets for Backwards {
+    ///     const BOUNDS: &[f64] = &[2.0, 1.0];
+    /// }
+    /// let hist = Histogram::<Backwards>::new();
+    /// ```
+    const BOUNDS: &'static [f64];
+}
+
+const fn valid_bounds(bounds: &[f64]) -> bool {
+    if bounds.is_empty() {
+        return false;
+    }
+    let mut i = 0;
+    while i < bounds.len() {
+        if !bounds[i].is_finite() || (i > 0 && bounds[i - 1] >= bounds[i]) {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// The number of values which fell into each of a fixed set of buckets
+///
+/// Unlike [`QuantileSketch`](super::QuantileSketch), the buckets are chosen
+/// up-front (see [`Buckets`]), so the size of each entry is fixed and the
+/// counts for any bound are exact.  Quantiles can only be estimated to within
+/// a bucket though.
+///
+/// ```
+/// # use compactor::{Aggregate, aggregate::{Buckets, Histogram}};
+/// struct Powers;
+/// impl Buckets for Powers {
+///     const BOUNDS: &[f64] = &[0.1, 0.2, 0.4, 0.8, 1.6, 3.2, 6.4, 12.8];
+/// }
+/// let mut hist = Histogram::<Powers>::from(0.15);
+/// for x in [0.3, 0.35, 0.5, 2.0, 100.0] {
+///     hist.merge(Histogram::from(x));
+/// }
+/// assert_eq!(hist.count(), 6);
+/// assert_eq!(hist.fraction_below(0.4), Some(0.5));
+/// ```
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(bound = ""))]
+pub struct Histogram<B> {
+    /// One for each bound, plus one for the overflow
+    counts: Vec<u64>,
+    sum: f64,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    buckets: PhantomData<B>,
+}
+
+impl<B: Buckets> Default for Histogram<B> {
+    fn default() -> Self {
+        Histogram::new()
+    }
+}
+
+impl<B: Buckets> From<f64> for Histogram<B> {
+    /// A histogram containing the single value `x`
+    fn from(x: f64) -> Self {
+        let mut hist = Histogram::new();
+        hist.insert(x);
+        hist
+    }
+}
+
+impl<B: Buckets> Histogram<B> {
+    /// An empty histogram
+    pub fn new() -> Self {
+        const { assert!(valid_bounds(B::BOUNDS), "Bad histogram bounds") };
+        Histogram {
+            counts: vec![0; B::BOUNDS.len() + 1],
+            sum: 0.0,
+            buckets: PhantomData,
+        }
+    }
+
+    /// Add a value to the histogram.  NaNs are ignored.
+    pub fn insert(&mut self, x: f64) {
+        if x.is_nan() {
+            return;
+        }
+        let i = B::BOUNDS.partition_point(|b| *b < x);
+        self.counts[i] += 1;
+        self.sum += x;
+    }
+
+    pub fn bounds(&self) -> &'static [f64] {
+        B::BOUNDS
+    }
+
+    /// The number of values in each bucket (_not_ cumulative).  The last one
+    /// is for values bigger than all the bounds.
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    /// The lower and upper bounds of bucket `i`.  Like Prometheus, we assume
+    /// the first bucket starts at zero (unless its bound is negative).
+    fn range(&self, i: usize) -> (f64, f64) {
+        let bounds = B::BOUNDS;
+        let hi = bounds[i];
+        let lo = match i {
+            0 => hi.min(0.0),
+            _ => bounds[i - 1],
+        };
+        (lo, hi)
+    }
+
+    /// The fraction of values which were `<= threshold`.  This is exact if
+    /// `threshold` is one of the bounds; otherwise it's interpolated linearly
+    /// within the bucket.  `None` if the histogram is empty.
+    pub fn fraction_below(&self, threshold: f64) -> Option<f64> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let i = B::BOUNDS.partition_point(|b| *b < threshold);
+        let mut below = self.counts[..i].iter().sum::<u64>() as f64;
+        if i < B::BOUNDS.len() {
+            let (lo, hi) = self.range(i);
+            if threshold >= lo {
+                below +=
+                    self.counts[i] as f64 * (threshold - lo) / (hi - lo).max(f64::MIN_POSITIVE);
+            }
+        }
+        Some(below / count as f64)
+    }
+
+    /// An estimate of the `q`-quantile, where `q` is between 0 and 1,
+    /// interpolated linearly within the bucket (like `histogram_quantile` in
+    /// Prometheus).  If it falls in the overflow bucket, the biggest bound is
+    /// returned.  `None` if the histogram is empty.
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = q.clamp(0.0, 1.0) * count as f64;
+        let mut seen = 0;
+        for (i, &n) in self.counts.iter().enumerate().take(B::BOUNDS.len()) {
+            if n > 0 && (seen + n) as f64 >= rank {
+                let (lo, hi) = self.range(i);
+                return Some(lo + (hi - lo) * (rank - seen as f64) / n as f64);
+            }
+            seen += n;
+        }
+        B::BOUNDS.last().copied()
+    }
+}
+
+impl<B> Aggregate for Histogram<B> {
+    fn merge(&mut self, other: Self) {
+        for (x, y) in self.counts.iter_mut().zip(other.counts) {
+            *x += y;
+        }
+        self.sum += other.sum;
+    }
+}
+
+impl<B> CommutativeAggregate for Histogram<B> {}
+
+// Not derived, since they'd need `B` to implement them too
+
+impl<B> Clone for Histogram<B> {
+    fn clone(&self) -> Self {
+        Histogram {
+            counts: self.counts.clone(),
+            sum: self.sum,
+            buckets: PhantomData,
+        }
+    }
+}
+
+impl<B> PartialEq for Histogram<B> {
+    fn eq(&self, other: &Self) -> bool {
+        self.counts == other.counts && self.sum == other.sum
+    }
+}
+
+impl<B> fmt::Debug for Histogram<B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Histogram")
+            .field("counts", &self.counts)
+            .field("sum", &self.sum)
+            .finish()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, B: Buckets> serde::Deserialize<'de> for Histogram<B> {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        #[derive(serde::Deserialize)]
+        struct Raw {
+            counts: Vec<u64>,
+            sum: f64,
+        }
+        let Raw { counts, sum } = Raw::deserialize(d)?;
+        if counts.len() != B::BOUNDS.len() + 1 {
+            return Err(serde::de::Error::custom(
+                "Wrong number of histogram buckets",
+            ));
+        }
+        Ok(Histogram {
+            counts,
+            sum,
+            buckets: PhantomData,
+        })
+    }
+}
+
+/// The buckets are part of the type, so only the counts are stored
+impl<B: Buckets> Codec for Histogram<B> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        for n in &self.counts {
+            encode_varint(*n, buf);
+        }
+        self.sum.encode(buf);
+    }
+    fn decode(buf: &mut &[u8]) -> io::Result<Self> {
+        let mut hist = Histogram::new();
+        for n in &mut hist.counts {
+            *n = decode_varint(buf)?;
+        }
+        hist.sum = f64::decode(buf)?;
+        Ok(hist)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Latency;
+    impl Buckets for Latency {
+        const BOUNDS: &[f64] = &[0.1, 0.25, 0.5, 1.0];
+    }
+
+    #[test]
+    fn test_bounds() {
+        assert!(valid_bounds(&[-1.0, 0.0, 2.5]));
+        assert!(!valid_bounds(&[]));
+        assert!(!valid_bounds(&[1.0, 1.0]));
+        assert!(!valid_bounds(&[2.0, 1.0]));
+        assert!(!valid_bounds(&[1.0, f64::INFINITY]));
+        assert!(!valid_bounds(&[f64::NAN]));
+    }
+
+    #[test]
+    fn test_histogram() {
+        let mut hist = Histogram::<Latency>::new();
+        assert_eq!(hist.quantile(0.5), None);
+        assert_eq!(hist.fraction_below(0.5), None);
+        for x in [0.05, 0.1, 0.2, 0.3, 0.4, 0.45, 0.5, 0.7, 2.0, f64::NAN] {
+            hist.merge(Histogram::from(x));
+        }
+        // Bounds are inclusive
+        assert_eq!(hist.counts(), &[2, 1, 4, 1, 1]);
+        assert_eq!(hist.count(), 9);
+        assert!((hist.sum() - 4.7).abs() < 1e-9);
+
+        assert_eq!(hist.fraction_below(0.5), Some(7.0 / 9.0));
+        assert_eq!(hist.fraction_below(1.0), Some(8.0 / 9.0));
+        assert_eq!(hist.fraction_below(5.0), Some(8.0 / 9.0));
+        assert_eq!(hist.fraction_below(0.75), Some(7.5 / 9.0));
+        assert_eq!(hist.fraction_below(-1.0), Some(0.0));
+
+        let quantile = |q: f64| hist.quantile(q).unwrap();
+        assert_eq!(quantile(0.0), 0.0);
+        assert!((quantile(2.0 / 9.0) - 0.1).abs() < 1e-9);
+        assert!((quantile(5.0 / 9.0) - 0.375).abs() < 1e-9);
+        assert_eq!(quantile(1.0), 1.0);
+    }
+}
@@ -0,0 +1,224 @@
+//! Checks that an [`Aggregate`] impl obeys the laws it's supposed to
+//!
+//! Call these from your own tests.  Each one generates lots of random inputs
+//! with the function you give it, and panics if the law doesn't hold for
+//! some of them.  The generator is given a random `u64` to build each input
+//! from.  The inputs are the same every time, so failures are reproducible.
+//!
+//! ```
+//! # use compactor::aggregate::{Max, Mean, laws};
+//! laws::assert_associative(|x| Max(x as i16));
+//! laws::assert_commutative(|x| Max(x as i16));
+//!
+//! // Floating-point arithmetic isn't quite associative
+//! laws::assert_associative_by(
+//!     |x| Mean::from(x as f32 as f64),
+//!     |a, b| (a.mean() - b.mean()).abs() <= 1e-9 * a.mean().abs(),
+//! );
+//! ```
+
+use super::{Aggregate, CommutativeAggregate, InvertibleAggregate, rng::Rng};
+use std::fmt::Debug;
+
+const TRIALS: usize = 256;
+
+/// Panics unless `(a + b) + c == a + (b + c)`
+pub fn assert_associative<T>(generate: impl FnMut(u64) -> T)
+where
+    T: Aggregate + Clone + PartialEq + Debug,
+{
+    assert_associative_by(generate, T::eq);
+}
+
+/// Like [`assert_associative`], but with a custom notion of equality
+pub fn assert_associative_by<T>(mut generate: impl FnMut(u64) -> T, eq: impl Fn(&T, &T) -> bool)
+where
+    T: Aggregate + Clone + Debug,
+{
+    let mut rng = Rng(0);
+    for _ in 0..TRIALS {
+        let a = generate(rng.next());
+        let b = generate(rng.next());
+        let c = generate(rng.next());
+        let mut left = a.clone();
+        left.merge(b.clone());
+        left.merge(c.clone());
+        let mut bc = b.clone();
+        bc.merge(c.clone());
+        let mut right = a.clone();
+        right.merge(bc);
+        assert!(
+            eq(&left, &right),
+            "Not associative:\na = {a:?}\nb = {b:?}\nc = {c:?}\n(a + b) + c = {left:?}\na + (b + c) = {right:?}"
+        );
+    }
+}
+
+/// Panics unless `a + b == b + a`
+pub fn assert_commutative<T>(generate: impl FnMut(u64) -> T)
+where
+    T: CommutativeAggregate + Clone + PartialEq + Debug,
+{
+    assert_commutative_by(generate, T::eq);
+}
+
+/// Like [`assert_commutative`], but with a custom notion of equality
+pub fn assert_commutative_by<T>(mut generate: impl FnMut(u64) -> T, eq: impl Fn(&T, &T) -> bool)
+where
+    T: CommutativeAggregate + Clone + Debug,
+{
+    let mut rng = Rng(1);
+    for _ in 0..TRIALS {
+        let a = generate(rng.next());
+        let b = generate(rng.next());
+        let mut ab = a.clone();
+        ab.merge(b.clone());
+        let mut ba = b.clone();
+        ba.merge(a.clone());
+        assert!(
+            eq(&ab, &ba),
+            "Not commutative:\na = {a:?}\nb = {b:?}\na + b = {ab:?}\nb + a = {ba:?}"
+        );
+    }
+}
+
+/// Panics unless `(a + b) - b == a`
+pub fn assert_invertible<T>(generate: impl FnMut(u64) -> T)
+where
+    T: InvertibleAggregate + Clone + PartialEq + Debug,
+{
+    assert_invertible_by(generate, T::eq);
+}
+
+/// Like [`assert_invertible`], but with a custom notion of equality
+pub fn assert_invertible_by<T>(mut generate: impl FnMut(u64) -> T, eq: impl Fn(&T, &T) -> bool)
+where
+    T: InvertibleAggregate + Clone + Debug,
+{
+    let mut rng = Rng(2);
+    for _ in 0..TRIALS {
+        let a = generate(rng.next());
+        let b = generate(rng.next());
+        let mut x = a.clone();
+        x.merge(b.clone());
+        let res = x.unmerge(b.clone());
+        assert!(
+            res.is_ok() && eq(&x, &a),
+            "Not invertible:\na = {a:?}\nb = {b:?}\n(a + b) - b = {x:?}"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregate::*;
+    use std::collections::BTreeMap;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() <= 1e-9 * a.abs().max(b.abs()).max(1.0)
+    }
+
+    #[test]
+    fn test_builtins() {
+        assert_associative(|x| vec![x]);
+        assert_associative(First);
+        assert_associative(Last);
+        assert_associative(|x| Candlestick::from(x as i8));
+
+        fn check<T: CommutativeAggregate + Clone + PartialEq + Debug>(generate: impl Fn(u64) -> T) {
+            assert_associative(&generate);
+            assert_commutative(&generate);
+        }
+        check(|x| Min(x as i32));
+        check(|x| Max(x as i32));
+        check(|x| Sum(x as u16 as u32));
+        check(|x| Count(x % 10));
+        check(|x| Mean::from(x as i32 as i64));
+        check(|x| Some(Max(x)).filter(|_| x % 3 != 0));
+        check(|x| (Min(x as u8), Max(x as u8), Count(1)));
+        check(|x| [Sum(x as u8 as u16), Sum((x >> 8) as u8 as u16)]);
+        check(|x| BTreeMap::from([(x % 4, Count(1)), (x % 7, Count(2))]));
+        check(|x| {
+            let mut hll = HyperLogLog::new(6);
+            hll.push_hash(x);
+            hll.push_hash(x.rotate_left(17));
+            hll
+        });
+        struct Tens;
+        impl Buckets for Tens {
+            const BOUNDS: &[f64] = &[0.0, 10.0, 20.0, 30.0, 40.0, 50.0, 60.0, 70.0];
+        }
+        check(|x| Histogram::<Tens>::from((x % 100) as f64));
+        check(|x| {
+            let mut sketch = QuantileSketch::new(0.01, 4);
+            sketch.insert((x % 1000) as f64 - 500.0);
+            sketch.insert((x >> 32) as f64);
+            sketch
+        });
+        // Mixed settings aren't associative, since re-binning twice loses more
+        // than re-binning once, but they are still commutative
+        assert_commutative(|x| {
+            let alpha = [0.01, 0.02, 0.05][x as usize % 3];
+            let max_bins = [4, 8, 2048][(x >> 2) as usize % 3];
+            let mut sketch = QuantileSketch::new(alpha, max_bins);
+            sketch.insert((x % 1000) as f64 - 500.0);
+            sketch.insert((x >> 32) as f64);
+            sketch
+        });
+
+        let moments = |x: u64| Moments::from((x % 1000) as f64);
+        let eq = |a: &Moments, b: &Moments| {
+            a.count() == b.count() && close(a.mean(), b.mean()) && close(a.variance(), b.variance())
+        };
+        assert_associative_by(moments, eq);
+        assert_commutative_by(moments, eq);
+        assert_invertible_by(
+            |x| {
+                let mut m = moments(x);
+                m.merge(moments(x >> 10));
+                m.merge(moments(x >> 20));
+                m
+            },
+            eq,
+        );
+
+        assert_invertible(|x| Sum(x as u16 as i64));
+        assert_invertible(|x| Count(x % 10));
+        assert_invertible(|x| Mean::from(x as i32 as i64));
+        assert_invertible(|x| (Count(1), [Sum(x as u8 as u32); 3]));
+    }
+
+    #[test]
+    #[should_panic(expected = "Not commutative")]
+    fn test_not_commutative() {
+        #[derive(Clone, PartialEq, Debug)]
+        struct Bad(Vec<u64>);
+        impl Aggregate for Bad {
+            fn merge(&mut self, other: Self) {
+                self.0.merge(other.0);
+            }
+        }
+        impl CommutativeAggregate for Bad {}
+        assert_commutative(|x| Bad(vec![x]));
+    }
+
+    #[test]
+    #[should_panic(expected = "Not invertible")]
+    fn test_not_invertible() {
+        assert_invertible(|x| Sum(x as u8 as f64 / 3.0));
+    }
+
+    #[test]
+    #[should_panic(expected = "Not associative")]
+    fn test_not_associative() {
+        #[derive(Clone, PartialEq, Debug)]
+        struct Diff(i64);
+        impl Aggregate for Diff {
+            fn merge(&mut self, other: Self) {
+                self.0 -= other.0;
+            }
+        }
+        assert_associative(|x| Diff(x as i8 as i64));
+    }
+}
@@ -0,0 +1,206 @@
+use super::{Aggregate, rng::Rng};
+use crate::codec::{Codec, decode_varint, encode_varint};
+use std::io;
+
+/// A uniform random sample of at most `N` of the values
+///
+/// Unlike a `Vec`, this stays the same size however many values are merged
+/// into it, so coarse buckets still hold some representative raw values.
+/// Merging takes the number of values each side has seen into account, so
+/// every value is equally likely to be in the sample, however the values were
+/// grouped into buckets.
+///
+/// Merging is random, so the result isn't exactly associative, but its
+/// distribution is.
+///
+/// ```
+/// # use compactor::{Aggregate, aggregate::Reservoir};
+/// let mut x = Reservoir::<u32, 10>::from(0);
+/// for y in 1..1000 {
+///     x.merge(Reservoir::from(y));
+/// }
+/// assert_eq!(x.count(), 1000);
+/// assert_eq!(x.samples().len(), 10);
+/// ```
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Reservoir<T, const N: usize> {
+    samples: Vec<T>,
+    /// The number of values which the samples were drawn from
+    count: u64,
+    /// The state of the RNG
+    seed: u64,
+}
+
+impl<T, const N: usize> Default for Reservoir<T, N> {
+    fn default() -> Self {
+        Reservoir {
+            samples: vec![],
+            count: 0,
+            seed: Rng::seed(),
+        }
+    }
+}
+
+impl<T, const N: usize> From<T> for Reservoir<T, N> {
+    fn from(x: T) -> Self {
+        Reservoir::with_seed(x, Rng::seed())
+    }
+}
+
+impl<T, const N: usize> Reservoir<T, N> {
+    /// A reservoir containing `x`, whose merges are random but reproducible.
+    /// The seeds of both sides determine the result of a merge.
+    pub fn with_seed(x: T, seed: u64) -> Self {
+        Reservoir {
+            samples: if N > 0 { vec![x] } else { vec![] },
+            count: 1,
+            seed,
+        }
+    }
+
+    /// In no particular order
+    pub fn samples(&self) -> &[T] {
+        &self.samples
+    }
+
+    pub fn into_samples(self) -> Vec<T> {
+        self.samples
+    }
+
+    /// The number of values which the samples were drawn from
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+impl<T, const N: usize> Aggregate for Reservoir<T, N> {
+    fn merge(&mut self, mut other: Self) {
+        let total = self.count + other.count;
+        let mut rng = Rng(self.seed ^ other.seed.rotate_left(32) ^ total);
+        self.seed = rng.next();
+        if total <= N as u64 {
+            // Everything fits
+            self.samples.append(&mut other.samples);
+            self.count = total;
+            return;
+        }
+        // Draw from both sides without replacement, picking a side in
+        // proportion to the number of values it has left.  Each side's
+        // samples are a uniform sample of its values, and we never need more
+        // of them than it has.
+        let (mut xs, mut ys) = (std::mem::take(&mut self.samples), other.samples);
+        let (mut nx, mut ny) = (self.count, other.count);
+        let mut samples = Vec::with_capacity(N);
+        while samples.len() < N {
+            let side = if rng.below(nx + ny) < nx {
+                nx -= 1;
+                &mut xs
+            } else {
+                ny -= 1;
+                &mut ys
+            };
+            let i = rng.below(side.len() as u64) as usize;
+            samples.push(side.swap_remove(i));
+        }
+        self.samples = samples;
+        self.count = total;
+    }
+}
+
+impl<T: Codec, const N: usize> Codec for Reservoir<T, N> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        encode_varint(self.count, buf);
+        self.seed.encode(buf);
+        for x in &self.samples {
+            x.encode(buf);
+        }
+    }
+    fn decode(buf: &mut &[u8]) -> io::Result<Self> {
+        let count = decode_varint(buf)?;
+        let seed = u64::decode(buf)?;
+        // The number of samples is implied by the count
+        let len = count.min(N as u64) as usize;
+        let mut samples = Vec::with_capacity(len.min(buf.len()));
+        for _ in 0..len {
+            samples.push(T::decode(buf)?);
+        }
+        Ok(Reservoir {
+            samples,
+            count,
+            seed,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_small() {
+        let mut x = Reservoir::<u32, 4>::from(1);
+        x.merge(Reservoir::from(2));
+        x.merge(Reservoir::from(3));
+        assert_eq!(x.samples(), &[1, 2, 3]);
+        x.merge(Reservoir::default());
+        assert_eq!(x.count(), 3);
+        let mut x = Reservoir::<u32, 0>::from(1);
+        x.merge(Reservoir::from(2));
+        assert_eq!(x.count(), 2);
+        assert!(x.samples().is_empty());
+    }
+
+    /// Counts how often each of `0..n` ends up in the sample
+    fn histogram(n: u32, trials: u64, sample: impl Fn(u64) -> Reservoir<u32, 10>) -> Vec<u64> {
+        let mut hist = vec![0; n as usize];
+        for seed in 0..trials {
+            let x = sample(seed);
+            assert_eq!(x.count(), n as u64);
+            assert_eq!(x.samples().len(), 10);
+            for i in x.samples() {
+                hist[*i as usize] += 1;
+            }
+        }
+        hist
+    }
+
+    fn assert_uniform(hist: &[u64], expected: f64) {
+        for (i, n) in hist.iter().enumerate() {
+            let err = (*n as f64 - expected).abs() / expected;
+            assert!(err < 0.2, "{i}: {n} != {expected}");
+        }
+    }
+
+    #[test]
+    fn test_uniform() {
+        let leaf = |seed: u64, i: u32| Reservoir::with_seed(i, seed * 1000 + i as u64);
+        // One at a time, like pushing
+        let hist = histogram(100, 4000, |seed| {
+            let mut x = leaf(seed, 0);
+            for i in 1..100 {
+                x.merge(leaf(seed, i));
+            }
+            x
+        });
+        assert_uniform(&hist, 400.0);
+
+        // In buckets of different sizes, like compaction
+        let hist = histogram(100, 4000, |seed| {
+            let mut acc = Reservoir {
+                samples: vec![],
+                count: 0,
+                seed,
+            };
+            for range in [0..3, 3..40, 40..41, 41..100] {
+                let mut bucket = leaf(seed, range.start);
+                for i in range.start + 1..range.end {
+                    bucket.merge(leaf(seed, i));
+                }
+                acc.merge(bucket);
+            }
+            acc
+        });
+        assert_uniform(&hist, 400.0);
+    }
+}
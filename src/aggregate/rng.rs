@@ -0,0 +1,38 @@
+use std::{
+    cell::Cell,
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+};
+
+/// SplitMix64.  Not for anything important, but it's fast and the output
+/// is reproducible.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) struct Rng(pub(crate) u64);
+
+impl Rng {
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..n`
+    pub(crate) fn below(&mut self, n: u64) -> u64 {
+        ((self.next() as u128 * n as u128) >> 64) as u64
+    }
+
+    /// A different seed every time
+    pub(crate) fn seed() -> u64 {
+        thread_local! {
+            static STATE: Cell<Rng> = Cell::new(Rng(RandomState::new().build_hasher().finish()));
+        }
+        STATE.with(|state| {
+            let mut rng = state.get();
+            let seed = rng.next();
+            state.set(rng);
+            seed
+        })
+    }
+}
@@ -0,0 +1,469 @@
+use super::{Aggregate, CommutativeAggregate};
+use crate::codec::{Codec, decode_varint, encode_varint, invalid_data};
+use std::io;
+
+/// An approximate distribution of the values, for estimating quantiles
+///
+/// This is a [DDSketch](https://arxiv.org/abs/1908.10693).  Values are
+/// counted in logarithmically-sized bins, so any quantile can be estimated
+/// with a bounded _relative_ error.  Sketches are merged by adding up the
+/// counts in each bin, so the result of merging is the same however the
+/// values were grouped into buckets.
+///
+/// The number of bins is bounded too.  If there are too many, the bins for the
+/// lowest values are collapsed together, so the accuracy guarantee only holds
+/// for the higher quantiles.  (Usually these are the ones you care about.)
+///
+/// ```
+/// # use compactor::{Aggregate, aggregate::QuantileSketch};
+/// let mut x = QuantileSketch::from(1.0);
+/// for y in 2..=100 {
+///     x.merge(QuantileSketch::from(y as f64));
+/// }
+/// let p95 = x.quantile(0.95).unwrap();
+/// assert!((p95 - 95.0).abs() <= 95.0 * 0.01);
+/// ```
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct QuantileSketch {
+    alpha: f64,
+    max_bins: u32,
+    /// Sorted by key
+    positive: Vec<(i32, u64)>,
+    /// Sorted by key.  Note that higher keys are _lower_ values.
+    negative: Vec<(i32, u64)>,
+    zeros: u64,
+    min: f64,
+    max: f64,
+}
+
+impl Default for QuantileSketch {
+    /// 1% relative accuracy, and at most 2048 bins
+    fn default() -> Self {
+        QuantileSketch::new(0.01, 2048)
+    }
+}
+
+impl From<f64> for QuantileSketch {
+    /// A sketch with the default settings containing a single value
+    fn from(x: f64) -> Self {
+        let mut sketch = QuantileSketch::default();
+        sketch.insert(x);
+        sketch
+    }
+}
+
+impl QuantileSketch {
+    /// An empty sketch.  Quantile estimates will be within a factor of
+    /// `relative_accuracy` of the true value, so long as there are no more
+    /// than `max_bins` bins.
+    ///
+    /// The number of bins needed to cover values from `a` to `b` is about
+    /// `ln(b / a) / (2 * relative_accuracy)`.
+    ///
+    /// Panics unless `relative_accuracy` is between 0 and 1, and `max_bins`
+    /// is at least 1.
+    pub fn new(relative_accuracy: f64, max_bins: u32) -> Self {
+        assert!(relative_accuracy > 0.0 && relative_accuracy < 1.0);
+        assert!(max_bins >= 1);
+        QuantileSketch {
+            alpha: relative_accuracy,
+            max_bins,
+            positive: vec![],
+            negative: vec![],
+            zeros: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+
+    pub fn relative_accuracy(&self) -> f64 {
+        self.alpha
+    }
+
+    fn ln_gamma(&self) -> f64 {
+        ((1.0 + self.alpha) / (1.0 - self.alpha)).ln()
+    }
+
+    /// The bin which `x` belongs in.  `x` must be positive.
+    fn key(&self, x: f64) -> i32 {
+        (x.ln() / self.ln_gamma()).ceil() as i32
+    }
+
+    /// The value which represents the bin: the relative error is at most
+    /// `alpha` for every value in it
+    fn value(&self, key: i32) -> f64 {
+        let gamma = self.ln_gamma().exp();
+        2.0 * (key as f64 * self.ln_gamma()).exp() / (gamma + 1.0)
+    }
+
+    /// Add a value to the sketch.  NaNs are ignored.
+    pub fn insert(&mut self, x: f64) {
+        self.insert_n(x, 1);
+    }
+
+    fn insert_n(&mut self, x: f64, n: u64) {
+        if x.is_nan() || n == 0 {
+            return;
+        }
+        self.min = self.min.min(x);
+        self.max = self.max.max(x);
+        if x > 0.0 {
+            let key = self.key(x);
+            add(&mut self.positive, key, n);
+        } else if x < 0.0 {
+            let key = self.key(-x);
+            add(&mut self.negative, key, n);
+        } else {
+            self.zeros += n;
+        }
+        self.collapse();
+    }
+
+    /// The number of values
+    pub fn count(&self) -> u64 {
+        let count = |bins: &[(i32, u64)]| bins.iter().map(|x| x.1).sum::<u64>();
+        count(&self.positive) + count(&self.negative) + self.zeros
+    }
+
+    /// The number of bins currently in use
+    pub fn bins(&self) -> usize {
+        self.positive.len() + self.negative.len()
+    }
+
+    /// The exact minimum, or `None` if the sketch is empty
+    pub fn min(&self) -> Option<f64> {
+        (self.min <= self.max).then_some(self.min)
+    }
+
+    /// The exact maximum, or `None` if the sketch is empty
+    pub fn max(&self) -> Option<f64> {
+        (self.min <= self.max).then_some(self.max)
+    }
+
+    /// An estimate of the `q`-quantile, where `q` is between 0 and 1.  `None`
+    /// if the sketch is empty.
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = (q.clamp(0.0, 1.0) * (count - 1) as f64).floor() as u64;
+        // Go through the bins from the lowest value to the highest
+        let negative = self
+            .negative
+            .iter()
+            .rev()
+            .map(|(k, n)| (-self.value(*k), *n));
+        let zeros = std::iter::once((0.0, self.zeros));
+        let positive = self.positive.iter().map(|(k, n)| (self.value(*k), *n));
+        let mut seen = 0;
+        for (x, n) in negative.chain(zeros).chain(positive) {
+            seen += n;
+            if seen > rank {
+                return Some(x.clamp(self.min, self.max));
+            }
+        }
+        Some(self.max)
+    }
+
+    /// Move the values into bins for a different accuracy.  Doesn't collapse
+    /// the bins.
+    fn rebin(self, alpha: f64) -> Self {
+        if alpha == self.alpha {
+            return self;
+        }
+        let mut out = QuantileSketch {
+            alpha,
+            positive: vec![],
+            negative: vec![],
+            ..self
+        };
+        for (k, n) in &self.positive {
+            let key = out.key(self.value(*k));
+            add(&mut out.positive, key, *n);
+        }
+        for (k, n) in &self.negative {
+            let key = out.key(self.value(*k));
+            add(&mut out.negative, key, *n);
+        }
+        out
+    }
+
+    /// Reduce the number of bins to `max_bins`, by merging the ones for the
+    /// lowest values
+    fn collapse(&mut self) {
+        while self.bins() > self.max_bins as usize {
+            if let Some((_, n)) = self.negative.pop() {
+                // The most negative values are at the end.  Once they're all
+                // gone, the negatives get lumped in with the zeros.
+                match self.negative.last_mut() {
+                    Some(bin) => bin.1 += n,
+                    None => self.zeros += n,
+                }
+            } else {
+                // `max_bins` is at least 1, so there must be at least two
+                let (_, n) = self.positive.remove(0);
+                self.positive[0].1 += n;
+            }
+        }
+    }
+}
+
+/// Add `n` to the count for `key`
+fn add(bins: &mut Vec<(i32, u64)>, key: i32, n: u64) {
+    match bins.binary_search_by_key(&key, |x| x.0) {
+        Ok(i) => bins[i].1 += n,
+        Err(i) => bins.insert(i, (key, n)),
+    }
+}
+
+/// Add up the counts of two sets of bins
+fn merge_bins(xs: &[(i32, u64)], ys: &[(i32, u64)]) -> Vec<(i32, u64)> {
+    let mut out = Vec::with_capacity(xs.len().max(ys.len()));
+    let (mut xs, mut ys) = (xs.iter().peekable(), ys.iter().peekable());
+    loop {
+        let next = match (xs.peek(), ys.peek()) {
+            (Some(x), Some(y)) if x.0 == y.0 => {
+                let n = x.1 + y.1;
+                let key = x.0;
+                xs.next();
+                ys.next();
+                (key, n)
+            }
+            (Some(x), Some(y)) if x.0 < y.0 => *xs.next().unwrap(),
+            (Some(_), Some(_)) => *ys.next().unwrap(),
+            (Some(_), None) => *xs.next().unwrap(),
+            (None, Some(_)) => *ys.next().unwrap(),
+            (None, None) => break,
+        };
+        out.push(next);
+    }
+    out
+}
+
+impl Aggregate for QuantileSketch {
+    /// If the sketches have different settings, the result has the coarser
+    /// accuracy and the smaller number of bins of the two.  The values in the
+    /// more accurate sketch are re-binned, which adds to the error.
+    fn merge(&mut self, other: Self) {
+        let alpha = self.alpha.max(other.alpha);
+        let this = std::mem::take(self).rebin(alpha);
+        let other = other.rebin(alpha);
+        *self = QuantileSketch {
+            alpha,
+            max_bins: this.max_bins.min(other.max_bins),
+            positive: merge_bins(&this.positive, &other.positive),
+            negative: merge_bins(&this.negative, &other.negative),
+            zeros: this.zeros + other.zeros,
+            // The re-binned values are approximate, but these are still exact
+            min: this.min.min(other.min),
+            max: this.max.max(other.max),
+        };
+        self.collapse();
+    }
+}
+
+/// Mixed settings are resolved the same way whichever side they're on
+impl CommutativeAggregate for QuantileSketch {}
+
+impl Codec for QuantileSketch {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.alpha.encode(buf);
+        self.max_bins.encode(buf);
+        self.min.encode(buf);
+        self.max.encode(buf);
+        encode_varint(self.zeros, buf);
+        encode_bins(&self.positive, buf);
+        encode_bins(&self.negative, buf);
+    }
+    fn decode(buf: &mut &[u8]) -> io::Result<Self> {
+        let alpha = f64::decode(buf)?;
+        let max_bins = u32::decode(buf)?;
+        if !(alpha > 0.0 && alpha < 1.0) || max_bins == 0 {
+            return Err(invalid_data("Bad sketch parameters"));
+        }
+        let sketch = QuantileSketch {
+            alpha,
+            max_bins,
+            min: f64::decode(buf)?,
+            max: f64::decode(buf)?,
+            zeros: decode_varint(buf)?,
+            positive: decode_bins(buf)?,
+            negative: decode_bins(buf)?,
+        };
+        if sketch.bins() > max_bins as usize {
+            return Err(invalid_data("Too many bins"));
+        }
+        Ok(sketch)
+    }
+}
+
+/// The keys are sorted, so they're stored as deltas
+fn encode_bins(bins: &[(i32, u64)], buf: &mut Vec<u8>) {
+    encode_varint(bins.len() as u64, buf);
+    let mut prev = None;
+    for &(key, n) in bins {
+        match prev {
+            None => key.encode(buf),
+            Some(prev) => encode_varint(key.abs_diff(prev) as u64, buf),
+        }
+        encode_varint(n, buf);
+        prev = Some(key);
+    }
+}
+
+fn decode_bins(buf: &mut &[u8]) -> io::Result<Vec<(i32, u64)>> {
+    let len = decode_varint(buf)?;
+    let mut bins: Vec<(i32, u64)> = Vec::with_capacity((len as usize).min(buf.len()));
+    for _ in 0..len {
+        let key = match bins.last() {
+            None => i32::decode(buf)?,
+            Some(&(prev, _)) => u32::try_from(decode_varint(buf)?)
+                .ok()
+                .filter(|&delta| delta > 0)
+                .and_then(|delta| prev.checked_add_unsigned(delta))
+                .ok_or_else(|| invalid_data("Bins out of order"))?,
+        };
+        bins.push((key, decode_varint(buf)?));
+    }
+    Ok(bins)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The `q`-quantile, computed the slow way
+    fn exact(xs: &[f64], q: f64) -> f64 {
+        let mut xs = xs.to_vec();
+        xs.sort_by(f64::total_cmp);
+        xs[(q * (xs.len() - 1) as f64).floor() as usize]
+    }
+
+    fn assert_accurate(sketch: &QuantileSketch, xs: &[f64], qs: &[f64]) {
+        for &q in qs {
+            let (x, y) = (sketch.quantile(q).unwrap(), exact(xs, q));
+            let tolerance = sketch.relative_accuracy() * y.abs() + 1e-12;
+            assert!((x - y).abs() <= tolerance, "p{q}: {x} != {y}");
+        }
+    }
+
+    /// Some pseudo-random values, with a long tail
+    fn values(n: u32) -> Vec<f64> {
+        (0..n)
+            .map(|i| {
+                let x = ((i * 7919) % 1000) as f64 + 1.0;
+                x * x / 1000.0
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_decode() {
+        let mut sketch = QuantileSketch::new(0.02, 100);
+        for x in [1.0, 10.0, 100.0] {
+            sketch.insert(x);
+        }
+        let mut buf = vec![];
+        sketch.encode(&mut buf);
+        assert_eq!(QuantileSketch::decode(&mut &buf[..]).unwrap(), sketch);
+        // More bins than `max_bins` allows
+        buf[8..12].copy_from_slice(&2_u32.to_le_bytes());
+        assert!(QuantileSketch::decode(&mut &buf[..]).is_err());
+        buf[8..12].copy_from_slice(&0_u32.to_le_bytes());
+        assert!(QuantileSketch::decode(&mut &buf[..]).is_err());
+    }
+
+    #[test]
+    fn test_accuracy() {
+        let xs = values(10_000);
+        let mut sketch = QuantileSketch::default();
+        for x in &xs {
+            sketch.insert(*x);
+        }
+        assert_eq!(sketch.count(), 10_000);
+        assert_accurate(&sketch, &xs, &[0.0, 0.1, 0.5, 0.95, 0.99, 1.0]);
+        assert_eq!(sketch.min(), Some(0.001));
+        assert_eq!(sketch.max(), Some(1000.0));
+
+        // Negatives and zeros
+        let xs: Vec<f64> = (-500..=500).map(|x| x as f64 / 10.0).collect();
+        let mut sketch = QuantileSketch::new(0.05, 1000);
+        for x in &xs {
+            sketch.insert(*x);
+        }
+        assert_eq!(sketch.quantile(0.5), Some(0.0));
+        assert_accurate(&sketch, &xs, &[0.0, 0.01, 0.25, 0.75, 0.99, 1.0]);
+
+        assert_eq!(QuantileSketch::default().quantile(0.5), None);
+        assert_eq!(QuantileSketch::default().min(), None);
+        assert_eq!(QuantileSketch::from(f64::NAN).count(), 0);
+    }
+
+    #[test]
+    fn test_merge() {
+        let xs = values(200);
+        let sketch = |xs: &[f64]| {
+            let mut acc = QuantileSketch::default();
+            for x in xs {
+                acc.merge(QuantileSketch::from(*x));
+            }
+            acc
+        };
+        let all = sketch(&xs);
+        for split in 0..=xs.len() {
+            let mut acc = sketch(&xs[..split]);
+            acc.merge(sketch(&xs[split..]));
+            assert_eq!(acc, all);
+        }
+
+        // Different accuracies get re-binned, whichever way round they are
+        let mut acc = QuantileSketch::new(0.02, 2048);
+        acc.merge(all.clone());
+        let mut other = all.clone();
+        other.merge(QuantileSketch::new(0.02, 2048));
+        assert_eq!(acc, other);
+        assert_eq!(acc.relative_accuracy(), 0.02);
+        assert_eq!(acc.count(), 200);
+        assert_eq!(acc.max(), xs.iter().copied().reduce(f64::max));
+        let p50 = acc.quantile(0.5).unwrap();
+        assert!((p50 - exact(&xs, 0.5)).abs() <= 0.03 * p50);
+
+        // ...and so do different bin limits
+        let mut acc = QuantileSketch::new(0.01, 20);
+        acc.merge(all.clone());
+        let mut other = all;
+        other.merge(QuantileSketch::new(0.01, 20));
+        assert_eq!(acc, other);
+        assert_eq!(acc.bins(), 20);
+        assert_eq!(acc.count(), 200);
+    }
+
+    #[test]
+    fn test_bounded() {
+        let xs: Vec<f64> = (-60..=60).map(|i| 10_f64.powf(i as f64 / 10.0)).collect();
+        let mut sketch = QuantileSketch::new(0.01, 50);
+        for x in xs.iter().rev() {
+            sketch.insert(*x);
+            assert!(sketch.bins() <= 50);
+        }
+        assert_eq!(sketch.count(), xs.len() as u64);
+        assert_accurate(&sketch, &xs, &[0.75, 0.9, 0.99, 1.0]);
+        // The low values have been collapsed, but the min is still exact
+        assert!(sketch.quantile(0.1).unwrap() > exact(&xs, 0.1));
+        assert_eq!(sketch.min(), Some(1e-6));
+
+        // Negative values get collapsed first
+        let mut sketch = QuantileSketch::new(0.01, 3);
+        for x in [-100.0, -10.0, -1.0, 1.0, 10.0] {
+            sketch.insert(x);
+        }
+        assert_eq!(sketch.bins(), 3);
+        // -100 and -10 have been lumped in with -1
+        assert!((sketch.quantile(0.4).unwrap() + 1.0).abs() <= 0.01);
+        assert_eq!(sketch.min(), Some(-100.0));
+        assert_eq!(sketch.count(), 5);
+        assert!((sketch.quantile(1.0).unwrap() - 10.0).abs() <= 0.1);
+    }
+}
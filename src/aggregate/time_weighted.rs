@@ -0,0 +1,251 @@
+use super::Aggregate;
+use crate::{UnixTime, codec::Codec};
+use std::{io, marker::PhantomData, time::Duration};
+
+/// An aggregate which can be built from a single value and the time it was
+/// sampled at
+///
+/// Use this with [`Compactor::push_sample`](crate::Compactor::push_sample),
+/// which passes the exact time of the sample along, rather than the bucket it
+/// ends up in.
+pub trait FromSample<V>: Aggregate {
+    fn from_sample(t: UnixTime, x: V) -> Self;
+}
+
+/// How a gauge's value is assumed to change between two samples
+pub trait Interpolation {
+    /// The area under the curve between two samples `secs` seconds apart
+    fn area(x0: f64, x1: f64, secs: f64) -> f64;
+}
+
+/// The value stays the same until the next sample
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Step;
+
+impl Interpolation for Step {
+    fn area(x0: f64, _: f64, secs: f64) -> f64 {
+        x0 * secs
+    }
+}
+
+/// The value changes linearly from one sample to the next
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Linear;
+
+impl Interpolation for Linear {
+    fn area(x0: f64, x1: f64, secs: f64) -> f64 {
+        (x0 + x1) / 2.0 * secs
+    }
+}
+
+/// The mean, integral, min, and max of a gauge, weighted by time
+///
+/// Taking the plain mean of some samples over-weights periods where samples
+/// were taken more often.  This instead treats the samples as points on a
+/// curve (see [`Step`] and [`Linear`]), and takes the area under it.
+///
+/// The value before the first sample and after the last one isn't known, so
+/// the stats for a bucket cover the time from its first sample to its last.
+/// When buckets are merged, the gap between them is filled in, so a coarse
+/// bucket gives the same result as if all its samples had been in one bucket.
+///
+/// Buckets should be merged in time order (which is what a compactor does).
+/// If they're merged the other way round they get swapped, but if they
+/// overlap the result is only approximate.
+///
+/// ```
+/// # use compactor::{Compactor, Date, Resolution, Time, aggregate::{Step, TimeWeightedMean}};
+/// let mut queue_depth = Compactor::<TimeWeightedMean<Step>>::new()
+///     .keep_for_days(1, Resolution::Hour)
+///     .build()
+///     .unwrap();
+/// let date = Date { year: 2024, month: 3, day: 1 };
+/// let minute = |m| Time::from_hour(9).with_minute(m);
+/// queue_depth.push_sample(date, minute(0), 0.0).unwrap();
+/// // A burst of samples for the last 10 minutes
+/// for m in 50..60 {
+///     queue_depth.push_sample(date, minute(m), 12.0).unwrap();
+/// }
+/// let (_, _, x) = queue_depth.iter().next().unwrap();
+/// // The plain mean of the samples would be about 11
+/// assert!((x.mean() - 1.83).abs() < 0.01);
+/// assert_eq!(x.max(), 12.0);
+/// ```
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TimeWeightedMean<I = Step> {
+    first: (UnixTime, f64),
+    last: (UnixTime, f64),
+    /// The area under the curve from `first` to `last`, in value-seconds
+    integral: f64,
+    min: f64,
+    max: f64,
+    interpolation: PhantomData<I>,
+}
+
+impl<I> TimeWeightedMean<I> {
+    pub fn new(t: UnixTime, x: f64) -> Self {
+        TimeWeightedMean {
+            first: (t, x),
+            last: (t, x),
+            integral: 0.0,
+            min: x,
+            max: x,
+            interpolation: PhantomData,
+        }
+    }
+
+    /// The time-weighted mean.  If all the samples were taken at the same
+    /// time, it's the value of the first one.
+    pub fn mean(&self) -> f64 {
+        let secs = self.duration().as_secs_f64();
+        if secs > 0.0 {
+            self.integral / secs
+        } else {
+            self.first.1
+        }
+    }
+
+    /// The area under the curve from the first sample to the last, in
+    /// value-seconds
+    pub fn integral(&self) -> f64 {
+        self.integral
+    }
+
+    /// The time from the first sample to the last
+    pub fn duration(&self) -> Duration {
+        let millis = self.last.0.as_millis() - self.first.0.as_millis();
+        Duration::from_millis(millis.max(0) as u64)
+    }
+
+    /// The smallest value the gauge took
+    pub fn min(&self) -> f64 {
+        self.min
+    }
+
+    /// The biggest value the gauge took
+    pub fn max(&self) -> f64 {
+        self.max
+    }
+
+    pub fn first(&self) -> (UnixTime, f64) {
+        self.first
+    }
+
+    pub fn last(&self) -> (UnixTime, f64) {
+        self.last
+    }
+}
+
+impl<I: Interpolation> Aggregate for TimeWeightedMean<I> {
+    fn merge(&mut self, mut other: Self) {
+        if other.first.0 < self.first.0 {
+            std::mem::swap(self, &mut other);
+        }
+        let gap = other.first.0.as_millis() - self.last.0.as_millis();
+        if gap >= 0 {
+            self.integral += I::area(self.last.1, other.first.1, gap as f64 / 1000.0);
+        }
+        self.integral += other.integral;
+        if other.last.0 >= self.last.0 {
+            self.last = other.last;
+        }
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+}
+
+impl<I: Interpolation> FromSample<f64> for TimeWeightedMean<I> {
+    fn from_sample(t: UnixTime, x: f64) -> Self {
+        TimeWeightedMean::new(t, x)
+    }
+}
+
+impl<I> Codec for TimeWeightedMean<I> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.first.encode(buf);
+        self.last.encode(buf);
+        self.integral.encode(buf);
+        self.min.encode(buf);
+        self.max.encode(buf);
+    }
+    fn decode(buf: &mut &[u8]) -> io::Result<Self> {
+        Ok(TimeWeightedMean {
+            first: Codec::decode(buf)?,
+            last: Codec::decode(buf)?,
+            integral: f64::decode(buf)?,
+            min: f64::decode(buf)?,
+            max: f64::decode(buf)?,
+            interpolation: PhantomData,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples() -> Vec<(UnixTime, f64)> {
+        // Mostly idle, with a burst of samples near the end
+        let mut xs = vec![(0, 1.0), (600, 2.0), (1800, 0.0)];
+        xs.extend((0..20).map(|i| (3000 + i * 30, 10.0 + i as f64)));
+        xs.push((3600, 4.0));
+        xs.into_iter()
+            .map(|(t, x)| (UnixTime::from_secs(t), x))
+            .collect()
+    }
+
+    fn fold<I: Interpolation>(xs: &[(UnixTime, f64)]) -> TimeWeightedMean<I> {
+        let mut acc = TimeWeightedMean::new(xs[0].0, xs[0].1);
+        for (t, x) in &xs[1..] {
+            acc.merge(TimeWeightedMean::new(*t, *x));
+        }
+        acc
+    }
+
+    fn assert_close(x: f64, y: f64) {
+        assert!((x - y).abs() <= 1e-9 * y.abs().max(1.0), "{x} != {y}");
+    }
+
+    #[test]
+    fn test_time_weighted() {
+        let xs = samples();
+        let step = fold::<Step>(&xs);
+        // 1 for 10 minutes, 2 for 20 minutes, 0 for 20 minutes, then 10..29
+        // for 30 seconds each
+        let burst = (10..30).sum::<i32>() as f64 * 30.0;
+        assert_close(step.integral(), 600.0 + 2.0 * 1200.0 + burst);
+        assert_close(step.mean(), step.integral() / 3600.0);
+        assert_eq!(step.duration(), Duration::from_secs(3600));
+        assert_eq!((step.min(), step.max()), (0.0, 29.0));
+        assert_eq!(step.first(), (UnixTime::from_secs(0), 1.0));
+        assert_eq!(step.last(), (UnixTime::from_secs(3600), 4.0));
+
+        let linear = fold::<Linear>(&xs);
+        let burst = (10..29).map(|x| x as f64 + 0.5).sum::<f64>() * 30.0 + 16.5 * 30.0;
+        assert_close(linear.integral(), 900.0 + 1200.0 + 6000.0 + burst);
+
+        let one = TimeWeightedMean::<Step>::new(UnixTime::from_secs(5), 3.0);
+        assert_eq!(one.mean(), 3.0);
+        assert_eq!(one.integral(), 0.0);
+    }
+
+    #[test]
+    fn test_grouping() {
+        let xs = samples();
+        let all = fold::<Linear>(&xs);
+        for split in 1..xs.len() {
+            let mut acc = fold::<Linear>(&xs[..split]);
+            acc.merge(fold(&xs[split..]));
+            assert_close(acc.integral(), all.integral());
+            assert_eq!((acc.first, acc.last), (all.first, all.last));
+            // Merging the wrong way round
+            let mut acc = fold::<Linear>(&xs[split..]);
+            acc.merge(fold(&xs[..split]));
+            assert_close(acc.integral(), all.integral());
+            assert_eq!((acc.first, acc.last), (all.first, all.last));
+        }
+    }
+}
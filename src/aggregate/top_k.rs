@@ -0,0 +1,210 @@
+use super::Aggregate;
+use crate::codec::{Codec, decode_varint, encode_varint, invalid_data};
+use std::{cmp::Reverse, io};
+
+/// The most frequent values, with approximate counts
+///
+/// This is a [Misra-Gries summary](https://en.wikipedia.org/wiki/Misra%E2%80%93Gries_summary),
+/// which keeps at most `N` counters.  Counts are underestimates, by at most
+/// [`max_error`](Self::max_error).  In particular, any value which makes up
+/// more than `1 / (N + 1)` of the total is guaranteed to be present.
+///
+/// Merging uses the algorithm from "Mergeable Summaries" (Agarwal et al.),
+/// so the error bound holds however the values were grouped into buckets.
+/// The exact counts can depend on the grouping though, so it isn't exactly
+/// associative.
+///
+/// ```
+/// # use compactor::{Aggregate, aggregate::TopK};
+/// let mut x = TopK::<&str, 2>::from("/index");
+/// for path in ["/login", "/index", "/about", "/index"] {
+///     x.merge(TopK::from(path));
+/// }
+/// // It was really seen 3 times, but counts can be off by up to 1
+/// assert_eq!(x.top().next(), Some((&"/index", 2)));
+/// assert_eq!(x.max_error(), 1);
+/// ```
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TopK<K, const N: usize> {
+    /// Sorted by count, biggest first
+    counters: Vec<(K, u64)>,
+    /// The number of values seen, including ones without a counter
+    total: u64,
+}
+
+impl<K, const N: usize> Default for TopK<K, N> {
+    fn default() -> Self {
+        TopK {
+            counters: vec![],
+            total: 0,
+        }
+    }
+}
+
+impl<K, const N: usize> From<K> for TopK<K, N> {
+    fn from(key: K) -> Self {
+        TopK::with_count(key, 1)
+    }
+}
+
+impl<K, const N: usize> TopK<K, N> {
+    /// `key`, seen `count` times
+    pub fn with_count(key: K, count: u64) -> Self {
+        TopK {
+            counters: if N > 0 && count > 0 {
+                vec![(key, count)]
+            } else {
+                vec![]
+            },
+            total: count,
+        }
+    }
+
+    /// The values with counters, most frequent first
+    pub fn top(&self) -> impl Iterator<Item = (&K, u64)> {
+        self.counters.iter().map(|(k, n)| (k, *n))
+    }
+
+    /// The total number of values seen
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// The most that any count can be an underestimate by.  Values without a
+    /// counter may have been seen up to this many times.
+    pub fn max_error(&self) -> u64 {
+        let counted: u64 = self.counters.iter().map(|x| x.1).sum();
+        (self.total - counted) / (N as u64 + 1)
+    }
+}
+
+impl<K: PartialEq, const N: usize> TopK<K, N> {
+    /// A lower bound on the number of times `key` was seen
+    pub fn count(&self, key: &K) -> u64 {
+        self.counters
+            .iter()
+            .find(|(k, _)| k == key)
+            .map_or(0, |x| x.1)
+    }
+}
+
+impl<K: PartialEq, const N: usize> Aggregate for TopK<K, N> {
+    fn merge(&mut self, other: Self) {
+        self.total += other.total;
+        for (key, n) in other.counters {
+            match self.counters.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, m)) => *m += n,
+                None => self.counters.push((key, n)),
+            }
+        }
+        self.counters.sort_by_key(|x| Reverse(x.1));
+        if self.counters.len() > N {
+            // Take the (N+1)th biggest count off all of them.  At least one
+            // of the counters drops to zero, so there are at most N left.
+            let cut = self.counters[N].1;
+            self.counters.truncate(N);
+            self.counters.retain(|x| x.1 > cut);
+            for (_, n) in &mut self.counters {
+                *n -= cut;
+            }
+        }
+    }
+}
+
+impl<K: Codec, const N: usize> Codec for TopK<K, N> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        encode_varint(self.total, buf);
+        encode_varint(self.counters.len() as u64, buf);
+        for (k, n) in &self.counters {
+            k.encode(buf);
+            encode_varint(*n, buf);
+        }
+    }
+    fn decode(buf: &mut &[u8]) -> io::Result<Self> {
+        let total = decode_varint(buf)?;
+        let len = decode_varint(buf)?;
+        if len > N as u64 {
+            return Err(invalid_data("Too many counters"));
+        }
+        let mut counters = Vec::with_capacity(len as usize);
+        for _ in 0..len {
+            counters.push((K::decode(buf)?, decode_varint(buf)?));
+        }
+        Ok(TopK { counters, total })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A skewed stream of keys: key `k` appears about `1000 / (k + 1)` times
+    fn stream() -> Vec<u32> {
+        let mut xs = vec![];
+        for k in 0..50 {
+            for _ in 0..1000 / (k + 1) {
+                xs.push(k);
+            }
+        }
+        // Shuffle deterministically
+        for i in (1..xs.len()).rev() {
+            xs.swap(i, (i * 7919) % (i + 1));
+        }
+        xs
+    }
+
+    fn assert_bounds<const N: usize>(top: &TopK<u32, N>, xs: &[u32]) {
+        assert_eq!(top.total(), xs.len() as u64);
+        assert!(top.counters.len() <= N);
+        assert!(top.counters.windows(2).all(|w| w[0].1 >= w[1].1));
+        for k in 0..50 {
+            let exact = xs.iter().filter(|x| **x == k).count() as u64;
+            let estimate = top.count(&k);
+            assert!(estimate <= exact, "{k}: {estimate} > {exact}");
+            assert!(
+                estimate + top.max_error() >= exact,
+                "{k}: {estimate} << {exact}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_top_k() {
+        let xs = stream();
+        let mut top = TopK::<u32, 5>::default();
+        for x in &xs {
+            top.merge(TopK::from(*x));
+        }
+        assert_bounds(&top, &xs);
+        // Key 0 is more than 1/6 of the total, so it's guaranteed to be there
+        assert_eq!(top.top().next().unwrap().0, &0);
+        // With more counters, the order is more accurate
+        let mut top = TopK::<u32, 20>::default();
+        for x in &xs {
+            top.merge(TopK::from(*x));
+        }
+        assert_bounds(&top, &xs);
+        let keys: Vec<u32> = top.top().map(|x| *x.0).take(3).collect();
+        assert_eq!(keys, vec![0, 1, 2]);
+
+        // Merging summaries of different chunks
+        for chunk in [1, 7, 100, 1000] {
+            let mut top = TopK::<u32, 5>::default();
+            for xs in xs.chunks(chunk) {
+                let mut part = TopK::default();
+                for x in xs {
+                    part.merge(TopK::from(*x));
+                }
+                top.merge(part);
+            }
+            assert_bounds(&top, &xs);
+            assert_eq!(top.top().next().unwrap().0, &0);
+        }
+
+        let mut top = TopK::<u32, 0>::from(1);
+        top.merge(TopK::with_count(2, 10));
+        assert_eq!(top.top().count(), 0);
+        assert_eq!(top.max_error(), 11);
+    }
+}
@@ -0,0 +1,462 @@
+//! Encoding values as bytes, for storing them on disk
+//!
+//! Implement [`Codec`] for your data type to use it with a
+//! [`Store`](crate::store::Store).  The impls for the built-in types store
+//! numbers as fixed-width little-endian, and collections as a `u32` length
+//! followed by the items.  Numbers which are usually small, like the counts
+//! inside aggregates and the deltas between sorted keys or times, are LEB128
+//! varints instead.
+
+use crate::{Date, Resolution, Time, UnixTime, policy::Policy};
+use linearize::{Linearize, LinearizeExt};
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::{BuildHasher, Hash},
+    io,
+    time::Duration,
+};
+
+pub trait Codec: Sized {
+    /// Append the encoded value to `buf`
+    fn encode(&self, buf: &mut Vec<u8>);
+
+    /// Decode a value from the start of `buf`, and advance `buf` past it
+    fn decode(buf: &mut &[u8]) -> io::Result<Self>;
+}
+
+/// Split `n` bytes off the front of `buf`
+pub(crate) fn take<'a>(buf: &mut &'a [u8], n: usize) -> io::Result<&'a [u8]> {
+    if buf.len() < n {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let (xs, rest) = buf.split_at(n);
+    *buf = rest;
+    Ok(xs)
+}
+
+pub(crate) fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// LEB128
+pub(crate) fn encode_varint(mut x: u64, buf: &mut Vec<u8>) {
+    while x >= 0x80 {
+        buf.push(x as u8 | 0x80);
+        x >>= 7;
+    }
+    buf.push(x as u8);
+}
+
+pub(crate) fn decode_varint(buf: &mut &[u8]) -> io::Result<u64> {
+    let mut x = 0;
+    for shift in (0..64).step_by(7) {
+        let b = u8::decode(buf)?;
+        if shift == 63 && b > 1 {
+            break;
+        }
+        x |= ((b & 0x7f) as u64) << shift;
+        if b & 0x80 == 0 {
+            return Ok(x);
+        }
+    }
+    Err(invalid_data("Varint overflow"))
+}
+
+/// 64-bit FNV-1a
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325_u64;
+    for b in bytes {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+/// Start a file with magic bytes and a version number
+pub(crate) fn write_header(buf: &mut Vec<u8>, magic: &[u8; 4], version: u8) {
+    buf.extend_from_slice(magic);
+    version.encode(buf);
+}
+
+/// End a file with a checksum of everything before it
+pub(crate) fn write_checksum(buf: &mut Vec<u8>) {
+    fnv1a(buf).encode(buf);
+}
+
+/// Check the magic bytes, version, and trailing checksum, and return the
+/// bytes in between
+pub(crate) fn check_header<'a>(
+    bytes: &'a [u8],
+    magic: &[u8; 4],
+    version: u8,
+) -> io::Result<&'a [u8]> {
+    let Some((body, mut checksum)) = bytes.split_at_checked(bytes.len().saturating_sub(8)) else {
+        return Err(invalid_data("Truncated file"));
+    };
+    if u64::decode(&mut checksum)? != fnv1a(body) {
+        return Err(invalid_data("Bad checksum"));
+    }
+    let mut buf = body;
+    if take(&mut buf, 4)? != magic {
+        return Err(invalid_data("Bad magic bytes"));
+    }
+    if u8::decode(&mut buf)? != version {
+        return Err(invalid_data("Unsupported version"));
+    }
+    Ok(buf)
+}
+
+macro_rules! impl_codec_le_bytes {
+    ($($t:ty),*) => {$(
+        impl Codec for $t {
+            fn encode(&self, buf: &mut Vec<u8>) {
+                buf.extend_from_slice(&self.to_le_bytes());
+            }
+            fn decode(buf: &mut &[u8]) -> io::Result<Self> {
+                let xs = take(buf, size_of::<$t>())?;
+                Ok(<$t>::from_le_bytes(xs.try_into().unwrap()))
+            }
+        }
+    )*};
+}
+
+impl_codec_le_bytes!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128, f32, f64);
+
+impl Codec for bool {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(*self as u8);
+    }
+    fn decode(buf: &mut &[u8]) -> io::Result<Self> {
+        match u8::decode(buf)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(invalid_data("Bad bool")),
+        }
+    }
+}
+
+impl<T: Codec> Codec for Option<T> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.is_some().encode(buf);
+        if let Some(x) = self {
+            x.encode(buf);
+        }
+    }
+    fn decode(buf: &mut &[u8]) -> io::Result<Self> {
+        if bool::decode(buf)? {
+            Ok(Some(T::decode(buf)?))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl<T: Codec> Codec for Vec<T> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        (self.len() as u32).encode(buf);
+        for x in self {
+            x.encode(buf);
+        }
+    }
+    fn decode(buf: &mut &[u8]) -> io::Result<Self> {
+        let len = u32::decode(buf)?;
+        // Don't trust `len` for the allocation: it might be corrupt
+        let mut xs = Vec::with_capacity((len as usize).min(buf.len()));
+        for _ in 0..len {
+            xs.push(T::decode(buf)?);
+        }
+        Ok(xs)
+    }
+}
+
+impl<T: Codec, const N: usize> Codec for [T; N] {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        for x in self {
+            x.encode(buf);
+        }
+    }
+    fn decode(buf: &mut &[u8]) -> io::Result<Self> {
+        let xs = (0..N)
+            .map(|_| T::decode(buf))
+            .collect::<io::Result<Vec<T>>>()?;
+        Ok(xs.try_into().unwrap_or_else(|_| unreachable!()))
+    }
+}
+
+impl<K: Codec + Eq + Hash, V: Codec, S: BuildHasher + Default> Codec for HashMap<K, V, S> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        (self.len() as u32).encode(buf);
+        for (k, v) in self {
+            k.encode(buf);
+            v.encode(buf);
+        }
+    }
+    fn decode(buf: &mut &[u8]) -> io::Result<Self> {
+        let len = u32::decode(buf)?;
+        let mut map =
+            HashMap::with_capacity_and_hasher((len as usize).min(buf.len()), S::default());
+        for _ in 0..len {
+            map.insert(K::decode(buf)?, V::decode(buf)?);
+        }
+        Ok(map)
+    }
+}
+
+impl<K: Codec + Ord, V: Codec> Codec for BTreeMap<K, V> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        (self.len() as u32).encode(buf);
+        for (k, v) in self {
+            k.encode(buf);
+            v.encode(buf);
+        }
+    }
+    fn decode(buf: &mut &[u8]) -> io::Result<Self> {
+        let len = u32::decode(buf)?;
+        let mut map = BTreeMap::new();
+        for _ in 0..len {
+            map.insert(K::decode(buf)?, V::decode(buf)?);
+        }
+        Ok(map)
+    }
+}
+
+macro_rules! impl_codec_tuple {
+    ($($t:ident $i:tt),*) => {
+        impl<$($t: Codec),*> Codec for ($($t,)*) {
+            #[allow(unused_variables)]
+            fn encode(&self, buf: &mut Vec<u8>) {
+                $(self.$i.encode(buf);)*
+            }
+            #[allow(unused_variables)]
+            fn decode(buf: &mut &[u8]) -> io::Result<Self> {
+                Ok(($($t::decode(buf)?,)*))
+            }
+        }
+    };
+}
+
+impl_codec_tuple!();
+impl_codec_tuple!(A 0);
+impl_codec_tuple!(A 0, B 1);
+impl_codec_tuple!(A 0, B 1, C 2);
+impl_codec_tuple!(A 0, B 1, C 2, D 3);
+impl_codec_tuple!(A 0, B 1, C 2, D 3, E 4);
+impl_codec_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+impl_codec_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+impl_codec_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+impl_codec_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8);
+impl_codec_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9);
+impl_codec_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9, K 10);
+impl_codec_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9, K 10, L 11);
+
+impl Codec for Date {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.year.encode(buf);
+        self.month.encode(buf);
+        self.day.encode(buf);
+    }
+    fn decode(buf: &mut &[u8]) -> io::Result<Self> {
+        let date = Date {
+            year: i16::decode(buf)?,
+            month: i8::decode(buf)?,
+            day: i8::decode(buf)?,
+        };
+        jiff::civil::Date::new(date.year, date.month, date.day)
+            .map_err(|_| invalid_data("Bad date"))?;
+        Ok(date)
+    }
+}
+
+impl Codec for Time {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.to_bits().encode(buf);
+    }
+    fn decode(buf: &mut &[u8]) -> io::Result<Self> {
+        Time::try_from_bits(u32::decode(buf)?).ok_or_else(|| invalid_data("Bad time"))
+    }
+}
+
+impl Codec for Duration {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.as_secs().encode(buf);
+        self.subsec_nanos().encode(buf);
+    }
+    fn decode(buf: &mut &[u8]) -> io::Result<Self> {
+        let secs = u64::decode(buf)?;
+        let nanos = u32::decode(buf)?;
+        if nanos >= 1_000_000_000 {
+            return Err(invalid_data("Bad duration"));
+        }
+        Ok(Duration::new(secs, nanos))
+    }
+}
+
+impl Codec for Resolution {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        (self.linearize() as u8).encode(buf);
+    }
+    fn decode(buf: &mut &[u8]) -> io::Result<Self> {
+        Resolution::from_linear(u8::decode(buf)? as usize)
+            .ok_or_else(|| invalid_data("Bad resolution"))
+    }
+}
+
+/// Stored as the arguments to the [`PolicyBuilder`] methods, so that decoding
+/// runs the same checks as building
+///
+/// [`PolicyBuilder`]: crate::policy::PolicyBuilder
+impl Codec for Policy {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let rules = self.tiers();
+        encode_varint(rules.len() as u64, buf);
+        for (res, days) in rules {
+            res.encode(buf);
+            days.encode(buf);
+        }
+        self.len_budget.map(|n| n as u64).encode(buf);
+    }
+    fn decode(buf: &mut &[u8]) -> io::Result<Self> {
+        let mut builder = Policy::new();
+        for _ in 0..decode_varint(buf)? {
+            let res = Resolution::decode(buf)?;
+            builder = builder.keep_for_days(u16::decode(buf)?, res);
+        }
+        if let Some(n) = Option::<u64>::decode(buf)? {
+            let n = usize::try_from(n).map_err(|_| invalid_data("Bad policy"))?;
+            builder = builder.len_budget(n);
+        }
+        builder.build().map_err(|_| invalid_data("Bad policy"))
+    }
+}
+
+impl Codec for UnixTime {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.as_millis().encode(buf);
+    }
+    fn decode(buf: &mut &[u8]) -> io::Result<Self> {
+        Ok(UnixTime::from_millis(i64::decode(buf)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Aggregate,
+        aggregate::{
+            Buckets, Count, Counter, Histogram, HyperLogLog, Linear, Max, Mean, Min, Moments,
+            QuantileSketch, Reservoir, Sum, TimeWeightedMean, TopK,
+        },
+    };
+
+    fn round_trip<T: Codec + PartialEq + std::fmt::Debug>(x: T) {
+        let mut buf = vec![];
+        x.encode(&mut buf);
+        let mut slice = &buf[..];
+        assert_eq!(T::decode(&mut slice).unwrap(), x);
+        assert!(slice.is_empty());
+        // Truncated input is an error, not a panic
+        for len in 0..buf.len() {
+            assert!(T::decode(&mut &buf[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        round_trip(0xdead_beef_u32);
+        round_trip(-1.5_f64);
+        round_trip(Some(vec![1_i16, -2, 3]));
+        round_trip(None::<u8>);
+        round_trip((Min(1.5), Max(2.5), Count(3)));
+        round_trip([Sum(1_u8), Sum(2)]);
+        round_trip(BTreeMap::from([(1_u8, Count(2)), (3, Count(4))]));
+        round_trip(HashMap::<_, _>::from([(1_u8, vec![2_u8])]));
+        round_trip(Date {
+            year: 2024,
+            month: 2,
+            day: 29,
+        });
+        round_trip(Time::new().with_hour(13).with_minute(2));
+        round_trip(Time::WHOLE_MONTH);
+        round_trip(Duration::from_millis(1500));
+        round_trip(Mean { sum: 2.5, count: 3 });
+        round_trip(Sum(-4_i64));
+        round_trip(Count(7));
+        round_trip(Moments::from(3.5));
+        let mut sketch = QuantileSketch::new(0.02, 100);
+        for x in [-3.0, 0.0, 0.5, 7.0, 7.1, 1e6] {
+            sketch.insert(x);
+        }
+        round_trip(sketch);
+        struct Powers;
+        impl Buckets for Powers {
+            const BOUNDS: &[f64] = &[1.0, 10.0, 100.0, 1000.0];
+        }
+        let mut hist = Histogram::<Powers>::from(5.0);
+        hist.insert(1e6);
+        round_trip(hist);
+        let mut hll = HyperLogLog::new(4);
+        hll.push_value("x");
+        round_trip(hll);
+        let mut reservoir = Reservoir::<u16, 2>::with_seed(1, 0);
+        reservoir.merge(Reservoir::from(2));
+        reservoir.merge(Reservoir::from(3));
+        round_trip(reservoir);
+        let mut top = TopK::<u8, 2>::with_count(1, 5);
+        top.merge(TopK::from(2));
+        top.merge(TopK::from(3));
+        round_trip(top);
+        let mut gauge = TimeWeightedMean::<Linear>::new(UnixTime::from_secs(-5), 1.0);
+        gauge.merge(TimeWeightedMean::new(UnixTime::from_secs(5), 3.0));
+        round_trip(gauge);
+        let mut counter = Counter::from(300);
+        counter.merge(Counter::from(1000));
+        counter.merge(Counter::from(20));
+        round_trip(counter);
+        round_trip(Resolution::Quarter);
+        round_trip(
+            Policy::new()
+                .keep_for_days(2, Resolution::Minute)
+                .keep_for_days(30, Resolution::Hour)
+                .keep_for_days(400, Resolution::Month)
+                .len_budget(1000)
+                .build()
+                .unwrap(),
+        );
+    }
+
+    #[test]
+    fn test_varint() {
+        for x in [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let mut buf = vec![];
+            encode_varint(x, &mut buf);
+            let mut slice = &buf[..];
+            assert_eq!(decode_varint(&mut slice).unwrap(), x);
+            assert!(slice.is_empty());
+        }
+        assert!(decode_varint(&mut &[0xff; 10][..]).is_err());
+        assert!(decode_varint(&mut &[0x80][..]).is_err());
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(bool::decode(&mut &[2][..]).is_err());
+        assert!(Time::decode(&mut &[0, 0, 0, 0][..]).is_err());
+        let mut buf = vec![];
+        2023_i16.encode(&mut buf);
+        buf.extend([2, 29]);
+        assert!(Date::decode(&mut &buf[..]).is_err());
+        // A huge length with no data behind it
+        assert!(Vec::<u8>::decode(&mut &[0xff, 0xff, 0xff, 0xff][..]).is_err());
+        assert!(Resolution::decode(&mut &[0xff][..]).is_err());
+        // Policies are checked just like when they're built
+        let mut buf = vec![];
+        encode_varint(2, &mut buf);
+        Resolution::Day.encode(&mut buf);
+        2_u16.encode(&mut buf);
+        Resolution::Hour.encode(&mut buf);
+        5_u16.encode(&mut buf);
+        None::<u64>.encode(&mut buf);
+        assert!(Policy::decode(&mut &buf[..]).is_err());
+    }
+}
@@ -1,43 +1,129 @@
 use crate::{
-    Aggregate, Date, Resolution, Time,
+    Aggregate, CommutativeAggregate, CompactorMap, Date, InvertibleAggregate, OutOfRange,
+    Resolution, Time, UnixTime,
+    aggregate::FromSample,
+    codec::{Codec, check_header, invalid_data, write_checksum, write_header},
     data::*,
+    grid::{cells, fill_grid},
     policy::{Policy, PolicyBuilder, PolicyError},
 };
-use std::{cmp::Ordering, marker::PhantomData};
+use core::fmt;
+use std::{
+    cmp::Ordering,
+    io,
+    marker::PhantomData,
+    ops::RangeBounds,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 /// Stores data at gradually diminishing resolution
 #[derive(Clone, PartialEq, Eq, Debug)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Compactor<T> {
-    policy: Policy,
-    data: CompactedData<T>,
+    // Shared between the series of a `CompactorMap`
+    #[cfg_attr(feature = "serde", serde(with = "arc_policy"))]
+    pub(crate) policy: Arc<Policy>,
+    pub(crate) data: CompactedData<T>,
+    // Only allowed for commutative aggregates, which we can't check when
+    // deserializing
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) late_horizon: Option<Duration>,
+    /// The newest date passed to `update_date`, which may be newer than any
+    /// of the data
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) date: Option<Date>,
+}
+
+/// serde can only handle `Arc`s with the "rc" feature, which would make a
+/// copy of the policy for every series in a `CompactorMap`.  `CompactorMap`
+/// shares them itself.
+#[cfg(feature = "serde")]
+mod arc_policy {
+    use crate::policy::Policy;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::sync::Arc;
+
+    pub fn serialize<S: Serializer>(policy: &Arc<Policy>, s: S) -> Result<S::Ok, S::Error> {
+        policy.serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Arc<Policy>, D::Error> {
+        Policy::deserialize(d).map(Arc::new)
+    }
 }
 
 impl<T> From<Policy> for Compactor<T> {
     fn from(policy: Policy) -> Self {
         Self {
-            policy,
+            policy: Arc::new(policy),
             data: CompactedData::default(),
+            late_horizon: None,
+            date: None,
         }
     }
 }
 
-pub struct CompactorBuilder<T>(PolicyBuilder, PhantomData<T>);
+pub struct CompactorBuilder<T> {
+    policy: PolicyBuilder,
+    late_horizon: Option<Duration>,
+    _phantom: PhantomData<T>,
+}
 
 impl<T> Default for CompactorBuilder<T> {
     fn default() -> Self {
-        CompactorBuilder(PolicyBuilder::default(), PhantomData)
+        CompactorBuilder {
+            policy: PolicyBuilder::default(),
+            late_horizon: None,
+            _phantom: PhantomData,
+        }
     }
 }
 
 impl<T> CompactorBuilder<T> {
     pub fn keep_for_days(mut self, num_days: u16, res: Resolution) -> Self {
-        self.0 = self.0.keep_for_days(num_days, res);
+        self.policy = self.policy.keep_for_days(num_days, res);
+        self
+    }
+
+    /// See [`PolicyBuilder::len_budget`]
+    pub fn len_budget(mut self, n: usize) -> Self {
+        self.policy = self.policy.len_budget(n);
         self
     }
 
     pub fn build(self) -> Result<Compactor<T>, PolicyError> {
-        self.0.build().map(Compactor::from)
+        let mut compactor = Compactor::from(self.policy.build()?);
+        compactor.late_horizon = self.late_horizon;
+        Ok(compactor)
+    }
+
+    /// Build a [`CompactorMap`], whose series all use these settings
+    pub fn build_map<K>(self) -> Result<CompactorMap<K, T>, PolicyError> {
+        Ok(CompactorMap::from(self.build()?))
+    }
+}
+
+impl<T: CommutativeAggregate> CompactorBuilder<T> {
+    /// Accept values which are pushed out-of-order, so long as they're no
+    /// more than `horizon` older than the newest value.
+    ///
+    /// A late value is merged into the bucket it belongs to, at whatever
+    /// resolution data of its age has already been compacted to.  Since it's
+    /// merged in after the values which arrived on time, this is only
+    /// available for aggregates where the order doesn't matter.
+    pub fn accept_late(mut self, horizon: Duration) -> Self {
+        self.late_horizon = Some(horizon);
+        self
+    }
+}
+
+impl<T: CommutativeAggregate> Compactor<T> {
+    /// Like [`CompactorBuilder::accept_late`], but for a compactor which has
+    /// already been built, eg. one loaded with [`Compactor::from_bytes`].
+    /// `None` stops accepting late values.
+    pub fn set_late_horizon(&mut self, horizon: Option<Duration>) {
+        self.late_horizon = horizon;
     }
 }
 
@@ -50,7 +136,42 @@ impl<T> Compactor<T> {
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum PushError {
+    /// The value is older than the newest value in the compactor (by more
+    /// than the lateness horizon, if one was set)
     NonMonotonic,
+    /// The timestamp is outside the range of [`Date`]
+    OutOfRange,
+}
+
+impl fmt::Display for PushError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PushError::NonMonotonic => f.write_str("The value is older than the newest value"),
+            PushError::OutOfRange => f.write_str("The timestamp is out of range"),
+        }
+    }
+}
+
+impl std::error::Error for PushError {}
+
+impl From<OutOfRange> for PushError {
+    fn from(OutOfRange: OutOfRange) -> Self {
+        PushError::OutOfRange
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RetractError {
+    /// There's no bucket containing that time: it was never pushed to, or
+    /// it's been discarded
+    NotFound,
+    /// The time is coarser than the buckets, so it's not clear which one to
+    /// take the value out of
+    TooCoarse,
+    /// The value is bigger than what's in the bucket (see
+    /// [`Underflow`](crate::aggregate::Underflow)).  The bucket is left as
+    /// it was.
+    Underflow,
 }
 
 impl<T: Aggregate> Compactor<T> {
@@ -60,48 +181,211 @@ impl<T: Aggregate> Compactor<T> {
         time: impl Into<Time>,
         x: T,
     ) -> Result<(), PushError> {
-        let date = date.into();
-        let mut time = time.into();
+        self.push_tracking(date.into(), time.into(), x, &mut Dirty::default())
+    }
+
+    /// [`Compactor::push`], recording the dates it changes in `dirty`
+    pub(crate) fn push_tracking(
+        &mut self,
+        today: Date,
+        mut time: Time,
+        x: T,
+        dirty: &mut Dirty,
+    ) -> Result<(), PushError> {
+        let mut date = today;
+        date.reduce_to(self.policy.max_res);
         time.reduce_to(self.policy.max_res);
 
         let Some(last) = self.data.0.last_mut() else {
             // It's the first item
             self.data.0.push((date, time, x));
+            dirty.mark(date);
             return Ok(());
         };
 
         // Check the date
         match last.0.cmp(&date) {
             Ordering::Equal => (), // The common case
-            Ordering::Greater => return Err(PushError::NonMonotonic),
+            Ordering::Greater => return self.push_late(date, time, x, dirty),
             Ordering::Less => {
                 // It's a new day.  We need to evaluate the policies
                 self.data.0.push((date, time, x));
-                self.data.apply_policy(&self.policy, date);
+                dirty.mark(date);
+                self.data.apply_policy(&self.policy, today, dirty);
+                self.data.enforce_len_budget(&self.policy, dirty);
                 return Ok(());
             }
         }
 
         // Check the time
         // `partial_cmp() == None` means that `time` is at a different
-        // resolution level to `last`.  In other words, there has just been
-        // a compaction, with no new data pushed since.  I don't think this
-        // is possible.
-        let ord = last.1.partial_cmp(&time).expect("Compacted head");
+        // resolution level to `last`.  In other words, `update_date` has
+        // compacted the newest data, with no new data pushed since.
+        let Some(ord) = last.1.partial_cmp(&time) else {
+            if first_milli(date, time) < first_milli(last.0, last.1) {
+                return self.push_late(date, time, x, dirty);
+            }
+            self.insert(date, time, x, dirty);
+            return Ok(());
+        };
         match ord {
-            Ordering::Less => self.data.0.push((date, time, x)), // no compaction
-            Ordering::Equal => last.2.merge(x),
-            Ordering::Greater => return Err(PushError::NonMonotonic),
+            Ordering::Less => {
+                // No compaction, unless we're over budget
+                self.data.0.push((date, time, x));
+                dirty.mark(date);
+                self.data.enforce_len_budget(&self.policy, dirty);
+            }
+            Ordering::Equal => {
+                last.2.merge(x);
+                dirty.mark(date);
+            }
+            Ordering::Greater => return self.push_late(date, time, x, dirty),
+        }
+        Ok(())
+    }
+
+    /// Insert a value which is older than the newest value
+    pub(crate) fn push_late(
+        &mut self,
+        date: Date,
+        time: Time,
+        x: T,
+        dirty: &mut Dirty,
+    ) -> Result<(), PushError> {
+        let horizon = self.late_horizon.ok_or(PushError::NonMonotonic)?;
+        let (last_date, last_time, _) = self.data.0.last().expect("Non-empty");
+        // Lateness is measured from the newest value, or from the start of
+        // the current date if that's later
+        let (now_date, now_millis) = match self.date {
+            Some(today) if today > *last_date => (today, 0),
+            _ => (*last_date, last_time.start_millis()),
+        };
+        let days = (now_date.to_civil() - date.to_civil()).get_days();
+        let now_millis = days as u64 * MILLIS_PER_DAY + now_millis as u64;
+        let lateness = Duration::from_millis(now_millis.saturating_sub(time.start_millis() as u64));
+        if lateness > horizon {
+            return Err(PushError::NonMonotonic);
         }
+        self.insert(date, time, x, dirty);
         Ok(())
     }
 
+    /// Insert a value which is older than the newest value, without checking
+    /// how old it is
+    pub(crate) fn insert(&mut self, mut date: Date, mut time: Time, x: T, dirty: &mut Dirty) {
+        let today = self.data.0.last().map(|(d, _, _)| *d).max(self.date);
+        let days = today.map_or(0, |today| (today.to_civil() - date.to_civil()).get_days());
+
+        // Data this old may already have been compacted...
+        let Some(res) = u16::try_from(days)
+            .ok()
+            .and_then(|days| self.policy.resolution_at(days))
+        else {
+            // ...or even discarded
+            return;
+        };
+        date.reduce_to(self.policy.max_res);
+        time.reduce_to(self.policy.max_res);
+        let idx = self
+            .data
+            .0
+            .partition_point(|(d, t, _)| last_milli(*d, *t) < first_milli(date, time));
+        match self.data.0.get_mut(idx) {
+            // Nothing is finer than `time`, so this contains it
+            Some((d, t, y)) if first_milli(*d, *t) <= last_milli(date, time) => {
+                y.merge(x);
+                dirty.mark(*d);
+            }
+            _ => {
+                // Periods longer than a day are only compacted once they're
+                // complete, so leave that to the next compaction
+                time.reduce_to(res.max(Resolution::Day));
+                self.data.0.insert(idx, (date, time, x));
+                dirty.mark(date);
+                self.data.enforce_len_budget(&self.policy, dirty);
+            }
+        }
+    }
+
+    /// Push a value to an aggregate which needs to know exactly when it was
+    /// sampled, like [`TimeWeightedMean`](crate::aggregate::TimeWeightedMean).
+    /// `time` is passed to the aggregate at full resolution.
+    pub fn push_sample<V>(
+        &mut self,
+        date: impl Into<Date>,
+        time: impl Into<Time>,
+        x: V,
+    ) -> Result<(), PushError>
+    where
+        T: FromSample<V>,
+    {
+        let (date, time) = (date.into(), time.into());
+        self.push(date, time, T::from_sample(UnixTime::from((date, time)), x))
+    }
+
+    /// Push a value for a UTC instant, at millisecond resolution
+    pub fn push_timestamp(&mut self, ts: impl Into<UnixTime>, x: T) -> Result<(), PushError> {
+        let (date, time) = ts.into().try_into()?;
+        self.push(date, time, x)
+    }
+
+    /// Push a value for a UTC instant, at millisecond resolution
+    pub fn push_system_time(&mut self, ts: SystemTime, x: T) -> Result<(), PushError> {
+        self.push_timestamp(ts, x)
+    }
+
     /// Update the current date without pushing any new data.  This can be used
     /// to force compaction.
     pub fn update_date(&mut self, date: impl Into<Date>) {
-        let date = date.into();
+        self.update_date_tracking(date.into(), &mut Dirty::default());
+    }
+
+    /// [`Compactor::update_date`], recording the dates it changes in `dirty`
+    pub(crate) fn update_date_tracking(&mut self, date: Date, dirty: &mut Dirty) {
+        self.date = self.date.max(Some(date));
         if self.data.0.last_mut().is_some_and(|last| date > last.0) {
-            self.data.apply_policy(&self.policy, date);
+            self.data.apply_policy(&self.policy, date, dirty);
+            self.data.enforce_len_budget(&self.policy, dirty);
+        }
+    }
+}
+
+impl<T: InvertibleAggregate> Compactor<T> {
+    /// Take a value back out of the bucket containing `date` and `time`, eg.
+    /// to apply a correction from upstream.  This works even if the bucket
+    /// has since been compacted.
+    ///
+    /// `x` should be something which was pushed to that bucket.  Buckets
+    /// aren't removed, even if they end up empty.
+    pub fn retract(
+        &mut self,
+        date: impl Into<Date>,
+        time: impl Into<Time>,
+        x: T,
+    ) -> Result<(), RetractError> {
+        let (mut date, mut time) = (date.into(), time.into());
+        if time.resolution() < self.policy.max_res {
+            return Err(RetractError::TooCoarse);
+        }
+        date.reduce_to(self.policy.max_res);
+        time.reduce_to(self.policy.max_res);
+        let idx = self
+            .data
+            .0
+            .partition_point(|(d, t, _)| last_milli(*d, *t) < first_milli(date, time));
+        let Some((d, t, y)) = self.data.0.get_mut(idx) else {
+            return Err(RetractError::NotFound);
+        };
+        if first_milli(*d, *t) > last_milli(date, time) {
+            Err(RetractError::NotFound)
+        } else if first_milli(*d, *t) > first_milli(date, time)
+            || last_milli(*d, *t) < last_milli(date, time)
+        {
+            // The resolutions nest, so this shouldn't happen, but it's better
+            // than taking the value out of the wrong bucket
+            Err(RetractError::TooCoarse)
+        } else {
+            y.unmerge(x).map_err(|_| RetractError::Underflow)
         }
     }
 }
@@ -146,6 +430,48 @@ impl<T> Compactor<T> {
     pub fn iter(&self) -> impl DoubleEndedIterator<Item = (Date, Time, &T)> {
         self.data.0.iter().map(|(d, t, x)| (*d, *t, x))
     }
+
+    /// The entries overlapping the half-open range `start..end`.  Goes from
+    /// old -> new.  See [`Compactor::range_bounds`] for details.
+    pub fn range(
+        &self,
+        start: (Date, Time),
+        end: (Date, Time),
+    ) -> impl DoubleEndedIterator<Item = (Date, Time, &T)> {
+        self.range_bounds(start..end)
+    }
+
+    /// The entries overlapping the given range.  Goes from old -> new.
+    ///
+    /// Each `Time` is an interval, and so are the bounds: an included bound
+    /// covers its whole interval, and an excluded bound covers none of it.
+    /// Older entries may have been compacted to a coarser resolution than the
+    /// bounds, in which case they only partially overlap the range.  Such
+    /// entries are included: you get every entry which covers _any_ part of
+    /// the range.
+    ///
+    /// ```
+    /// # use compactor::{Compactor, Date, Resolution, Time};
+    /// let mut compactor = Compactor::new()
+    ///     .keep_for_days(1, Resolution::Hour)
+    ///     .build()
+    ///     .unwrap();
+    /// let date = Date { year: 2024, month: 3, day: 1 };
+    /// for h in 0..24 {
+    ///     compactor.push(date, Time::from_hour(h), vec![h]).unwrap();
+    /// }
+    /// let xs = compactor
+    ///     .range_bounds((date, Time::from_hour(9))..=(date, Time::PM))
+    ///     .flat_map(|(_, _, x)| x)
+    ///     .collect::<Vec<_>>();
+    /// assert_eq!(xs.len(), 15);
+    /// ```
+    pub fn range_bounds(
+        &self,
+        range: impl RangeBounds<(Date, Time)>,
+    ) -> impl DoubleEndedIterator<Item = (Date, Time, &T)> {
+        self.data.range(range).iter().map(|(d, t, x)| (*d, *t, x))
+    }
 }
 
 // Should this be `where &T: Aggregate` instead?
@@ -157,8 +483,91 @@ impl<T: Aggregate + Clone> Compactor<T> {
     ) -> impl Iterator<Item = (Date, Time, T)> {
         with_max_res(res, self.data.0.iter().map(|(d, t, x)| (*d, *t, x.clone())))
     }
+
+    /// Every bucket at resolution `res` from `start` to `end` (inclusive),
+    /// with `None` for the ones with no data.  Goes from old -> new.
+    ///
+    /// Entries are merged up to `res` like in
+    /// [`iter_with_max_resolution`](Self::iter_with_max_resolution).  An entry
+    /// which has already been compacted to something coarser than `res` takes
+    /// the place of all the buckets it covers.  Use the methods in
+    /// [`FillGaps`](crate::grid::FillGaps) to fill in the empty buckets.
+    ///
+    /// ```
+    /// # use compactor::{Compactor, Date, Resolution, Time, aggregate::Count};
+    /// let mut compactor = Compactor::new()
+    ///     .keep_for_days(1, Resolution::Minute)
+    ///     .build()
+    ///     .unwrap();
+    /// let date = Date { year: 2024, month: 3, day: 1 };
+    /// compactor.push(date, Time::from_hour(9).with_minute(5), Count(1)).unwrap();
+    /// compactor.push(date, Time::from_hour(9).with_minute(25), Count(1)).unwrap();
+    /// compactor.push(date, Time::from_hour(11).with_minute(0), Count(1)).unwrap();
+    /// let counts: Vec<_> = compactor
+    ///     .iter_grid(Resolution::Hour, date, date)
+    ///     .map(|(_, _, x)| x.map_or(0, |x| x.0))
+    ///     .collect();
+    /// assert_eq!(counts.len(), 24);
+    /// assert_eq!(counts[8..12], [0, 2, 0, 1]);
+    /// ```
+    pub fn iter_grid(
+        &self,
+        res: Resolution,
+        start: impl Into<Date>,
+        end: impl Into<Date>,
+    ) -> impl Iterator<Item = (Date, Time, Option<T>)> {
+        let (start, end) = (start.into(), end.into());
+        // The first and last cells may stick out past `start` and `end`
+        let mut first = start;
+        first.reduce_to(res);
+        let xs = self
+            .range_bounds((first, Time::WHOLE_DAY)..=(end.period_end(res), Time::WHOLE_DAY))
+            .map(|(d, t, x)| (d, t, x.clone()));
+        fill_grid(cells(res, start, end), with_max_res(res, xs))
+    }
+}
+
+const MAGIC: &[u8; 4] = b"CMPC";
+const VERSION: u8 = 1;
+
+impl<T: Codec> Compactor<T> {
+    /// Encode the compactor in a compact binary format.  This is much smaller
+    /// than going via serde: each date is only stored once, and times are
+    /// stored as the difference from the previous one.
+    ///
+    /// The lateness horizon isn't included (with this or with serde), since
+    /// only commutative aggregates can have one.  Set it again with
+    /// [`Compactor::set_late_horizon`].
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![];
+        write_header(&mut buf, MAGIC, VERSION);
+        self.policy.encode(&mut buf);
+        self.data.encode(&mut buf);
+        self.date.encode(&mut buf);
+        write_checksum(&mut buf);
+        buf
+    }
+
+    /// Decode the output of [`Compactor::to_bytes()`].  Returns an error with
+    /// kind `InvalidData` if the bytes are corrupt.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let mut buf = check_header(bytes, MAGIC, VERSION)?;
+        let compactor = Compactor {
+            policy: Arc::new(Policy::decode(&mut buf)?),
+            data: CompactedData::decode(&mut buf)?,
+            late_horizon: None,
+            date: Codec::decode(&mut buf)?,
+        };
+        if !buf.is_empty() {
+            return Err(invalid_data("Trailing bytes"));
+        }
+        compactor.data.check(compactor.policy.max_res)?;
+        Ok(compactor)
+    }
 }
 
+const MILLIS_PER_DAY: u64 = 24 * 60 * 60 * 1000;
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -170,6 +579,28 @@ mod tests {
         Date { year, month, day }
     }
 
+    /// Like a `Vec`, but the order doesn't matter
+    #[derive(Clone, PartialEq, Debug)]
+    struct Bag(Vec<u32>);
+    impl Aggregate for Bag {
+        fn merge(&mut self, mut other: Self) {
+            self.0.append(&mut other.0);
+            self.0.sort();
+        }
+    }
+    impl CommutativeAggregate for Bag {}
+    impl Codec for Bag {
+        fn encode(&self, buf: &mut Vec<u8>) {
+            self.0.encode(buf);
+        }
+        fn decode(buf: &mut &[u8]) -> io::Result<Self> {
+            Ok(Bag(Codec::decode(buf)?))
+        }
+    }
+    fn bag<const N: usize>(xs: [u32; N]) -> Bag {
+        Bag(xs.into())
+    }
+
     #[test]
     fn test_one_day() -> Result<(), PushError> {
         let mut agg = Compactor::new()
@@ -330,6 +761,463 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_range() -> Result<(), PushError> {
+        let mut agg = Compactor::new()
+            .keep_for_days(1, Resolution::Hour)
+            .keep_for_days(2, Resolution::AmPm)
+            .build()
+            .unwrap();
+        for d in 1..=2 {
+            for h in [3, 9, 15, 21] {
+                agg.push(date(2024, 3, d), time(h, 0, 0), vec![(d, h)])?;
+            }
+        }
+        // Day 1 is at AM/PM resolution, day 2 at hour resolution
+        let range = |start, end| {
+            agg.range(start, end)
+                .flat_map(|(_, _, x)| x.clone())
+                .collect::<Vec<_>>()
+        };
+        let d1 = date(2024, 3, 1);
+        let d2 = date(2024, 3, 2);
+        assert_eq!(
+            range((d2, time(9, 0, 0)), (d2, time(21, 0, 0))),
+            vec![(2, 9), (2, 15)]
+        );
+        assert_eq!(
+            range((d2, time(9, 30, 0)), (d2, time(15, 30, 0))),
+            vec![(2, 9), (2, 15)]
+        );
+        assert_eq!(range((d2, Time::AM), (d2, Time::PM)), vec![(2, 3), (2, 9)]);
+        // The AM bucket partially overlaps, so it's included
+        assert_eq!(
+            range((d1, time(9, 0, 0)), (d2, time(9, 0, 0))),
+            vec![(1, 3), (1, 9), (1, 15), (1, 21), (2, 3)]
+        );
+        // Starting at noon excludes the AM bucket
+        assert_eq!(
+            range((d1, time(12, 0, 0)), (d2, Time::WHOLE_DAY)),
+            vec![(1, 15), (1, 21)]
+        );
+        assert_eq!(range((d2, time(15, 0, 0)), (d2, time(9, 0, 0))), vec![]);
+        assert_eq!(range((d2, time(15, 0, 0)), (d2, time(15, 0, 0))), vec![]);
+
+        use std::ops::Bound;
+        let xs = agg
+            .range_bounds((
+                Bound::Excluded((d1, Time::AM)),
+                Bound::Included((d2, Time::AM)),
+            ))
+            .flat_map(|(_, _, x)| x.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(xs, vec![(1, 15), (1, 21), (2, 3), (2, 9)]);
+        assert_eq!(agg.range_bounds(..).count(), agg.len());
+        assert_eq!(agg.range_bounds((d2, Time::PM)..).count(), 2);
+        assert_eq!(agg.range_bounds(..(d1, Time::PM)).count(), 1);
+        assert_eq!(
+            agg.range_bounds(..).rev().map(|x| x.1).collect::<Vec<_>>(),
+            agg.iter().rev().map(|x| x.1).collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_late() -> Result<(), PushError> {
+        let minute = |h, m| Time::from_hour(h).with_minute(m);
+        let mut agg = Compactor::new()
+            .keep_for_days(1, Resolution::Minute)
+            .keep_for_days(2, Resolution::Hour)
+            .keep_for_days(3, Resolution::Day)
+            .accept_late(Duration::from_secs(36 * 60 * 60))
+            .build()
+            .unwrap();
+        agg.push(date(2024, 3, 1), time(12, 0, 0), bag([1]))?;
+        agg.push(date(2024, 3, 1), time(12, 2, 0), bag([2]))?;
+        // Into an existing bucket
+        agg.push(date(2024, 3, 1), time(12, 0, 30), bag([3]))?;
+        // Into a new bucket
+        agg.push(date(2024, 3, 1), time(12, 1, 0), bag([4]))?;
+        assert_eq!(
+            agg.data.0,
+            vec![
+                (date(2024, 3, 1), minute(12, 0), bag([1, 3])),
+                (date(2024, 3, 1), minute(12, 1), bag([4])),
+                (date(2024, 3, 1), minute(12, 2), bag([2])),
+            ]
+        );
+        agg.push(date(2024, 3, 2), time(6, 0, 0), bag([5]))?;
+        // Yesterday's data has been compacted, so the late value is too
+        agg.push(date(2024, 3, 1), time(12, 59, 0), bag([6]))?;
+        agg.push(date(2024, 3, 1), time(13, 0, 0), bag([7]))?;
+        assert_eq!(
+            agg.data.0,
+            vec![
+                (date(2024, 3, 1), Time::from_hour(12), bag([1, 2, 3, 4, 6])),
+                (date(2024, 3, 1), Time::from_hour(13), bag([7])),
+                (date(2024, 3, 2), minute(6, 0), bag([5])),
+            ]
+        );
+        // Too late
+        assert_eq!(
+            agg.push(date(2024, 2, 29), time(12, 0, 0), bag([8])),
+            Err(PushError::NonMonotonic)
+        );
+        // Lateness is measured from the current date, even if nothing has
+        // been pushed for it yet
+        agg.update_date(date(2024, 3, 3));
+        assert_eq!(
+            agg.push(date(2024, 3, 1), time(11, 0, 0), bag([8])),
+            Err(PushError::NonMonotonic)
+        );
+        agg.push(date(2024, 3, 1), time(12, 0, 0), bag([9]))?;
+        agg.push(date(2024, 3, 2), time(7, 0, 0), bag([10]))?;
+        assert_eq!(
+            agg.data.0,
+            vec![
+                (
+                    date(2024, 3, 1),
+                    Time::WHOLE_DAY,
+                    bag([1, 2, 3, 4, 6, 7, 9])
+                ),
+                (date(2024, 3, 2), Time::from_hour(6), bag([5])),
+                (date(2024, 3, 2), Time::from_hour(7), bag([10])),
+            ]
+        );
+        // The horizon isn't saved
+        let mut loaded = Compactor::<Bag>::from_bytes(&agg.to_bytes()).unwrap();
+        assert_eq!(loaded.late_horizon, None);
+        loaded.set_late_horizon(agg.late_horizon);
+        assert_eq!(loaded, agg);
+        let mut strict = Compactor::new()
+            .keep_for_days(1, Resolution::Minute)
+            .build()
+            .unwrap();
+        strict.push(date(2024, 3, 1), time(12, 2, 0), vec![1])?;
+        assert_eq!(
+            strict.push(date(2024, 3, 1), time(12, 1, 0), vec![2]),
+            Err(PushError::NonMonotonic)
+        );
+
+        // More than `u16::MAX` days late is older than any policy can keep
+        let mut patient = Compactor::new()
+            .keep_for_days(20_000, Resolution::Day)
+            .accept_late(Duration::MAX)
+            .build()
+            .unwrap();
+        patient.push(date(2024, 3, 1), time(12, 0, 0), bag([1]))?;
+        patient.push(date(1800, 3, 1), time(12, 0, 0), bag([2]))?;
+        assert_eq!(patient.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_retract() -> Result<(), PushError> {
+        use crate::aggregate::Sum;
+        let mut agg = Compactor::new()
+            .keep_for_days(1, Resolution::Minute)
+            .keep_for_days(2, Resolution::Hour)
+            .keep_for_days(5, Resolution::Day)
+            .build()
+            .unwrap();
+        for d in 1..=3 {
+            for h in 0..24 {
+                agg.push(date(2024, 3, d), time(h, 0, 0), Sum(10))?;
+                agg.push(date(2024, 3, d), time(h, 30, 0), Sum(20))?;
+            }
+        }
+        let sums = |agg: &Compactor<Sum<i32>>| agg.iter().map(|x| x.2.0).collect::<Vec<_>>();
+        // Day 1 has been compacted to a single bucket
+        assert_eq!(sums(&agg)[..2], [720, 30]);
+        agg.retract(date(2024, 3, 1), time(5, 30, 0), Sum(20))
+            .unwrap();
+        agg.retract(date(2024, 3, 2), time(0, 1, 2), Sum(10))
+            .unwrap();
+        assert_eq!(sums(&agg)[..2], [700, 20]);
+        // Minute buckets
+        agg.retract(date(2024, 3, 3), time(23, 30, 45), Sum(15))
+            .unwrap();
+        assert_eq!(sums(&agg).last(), Some(&5));
+        assert_eq!(
+            agg.retract(date(2024, 3, 3), time(23, 31, 0), Sum(1)),
+            Err(RetractError::NotFound)
+        );
+        assert_eq!(
+            agg.retract(date(2024, 2, 1), time(0, 0, 0), Sum(1)),
+            Err(RetractError::NotFound)
+        );
+        // Could be any of the minutes in that day
+        assert_eq!(
+            agg.retract(date(2024, 3, 3), Time::WHOLE_DAY, Sum(1)),
+            Err(RetractError::TooCoarse)
+        );
+        assert_eq!(
+            agg.retract(date(2024, 3, 3), time(23, 30, 0), Sum(i32::MIN)),
+            Err(RetractError::Underflow)
+        );
+        assert_eq!(sums(&agg).last(), Some(&5));
+
+        // A bucket which is finer than the time being retracted
+        let mut agg = Compactor::new()
+            .keep_for_days(1, Resolution::Hour)
+            .build()
+            .unwrap();
+        agg.data.0.push((date(2024, 3, 1), time(9, 30, 0), Sum(1)));
+        assert_eq!(
+            agg.retract(date(2024, 3, 1), time(9, 0, 0), Sum(1)),
+            Err(RetractError::TooCoarse)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_iter_grid() -> Result<(), PushError> {
+        let mut agg = Compactor::new()
+            .keep_for_days(1, Resolution::Hour)
+            .keep_for_days(3, Resolution::Day)
+            .build()
+            .unwrap();
+        for d in 1..=3 {
+            for h in [3, 9, 10] {
+                agg.push(date(2024, 3, d), time(h, 0, 0), vec![d as u8, h])?;
+            }
+        }
+        let grid: Vec<_> = agg
+            .iter_grid(Resolution::AmPm, date(2024, 2, 29), date(2024, 3, 4))
+            .collect();
+        let expected = vec![
+            (date(2024, 2, 29), Time::AM, None),
+            (date(2024, 2, 29), Time::PM, None),
+            // Already compacted to days
+            (
+                date(2024, 3, 1),
+                Time::WHOLE_DAY,
+                Some(vec![1, 3, 1, 9, 1, 10]),
+            ),
+            (
+                date(2024, 3, 2),
+                Time::WHOLE_DAY,
+                Some(vec![2, 3, 2, 9, 2, 10]),
+            ),
+            (date(2024, 3, 3), Time::AM, Some(vec![3, 3, 3, 9, 3, 10])),
+            (date(2024, 3, 3), Time::PM, None),
+            (date(2024, 3, 4), Time::AM, None),
+            (date(2024, 3, 4), Time::PM, None),
+        ];
+        assert_eq!(grid, expected);
+
+        // Cells which are coarser than a day
+        let mut agg = Compactor::new()
+            .keep_for_days(30, Resolution::Day)
+            .build()
+            .unwrap();
+        // Mon 4th, Wed 6th, Thu 14th
+        for d in [4, 6, 14] {
+            agg.push(date(2024, 3, d), time(9, 0, 0), vec![d as u8])?;
+        }
+        // From Wed 6th to Tue 12th
+        let grid: Vec<_> = agg
+            .iter_grid(Resolution::Week, date(2024, 3, 6), date(2024, 3, 12))
+            .collect();
+        let expected = vec![
+            (date(2024, 3, 4), Time::WHOLE_WEEK, Some(vec![4, 6])),
+            (date(2024, 3, 11), Time::WHOLE_WEEK, Some(vec![14])),
+        ];
+        assert_eq!(grid, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_push_timestamp() -> Result<(), PushError> {
+        let mut agg = Compactor::new()
+            .keep_for_days(1, Resolution::Hour)
+            .build()
+            .unwrap();
+        agg.push_timestamp(UnixTime::from_secs(1_700_000_000), vec![1])?;
+        agg.push_system_time(
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_100),
+            vec![2],
+        )?;
+        assert_eq!(
+            agg.data.0,
+            vec![(date(2023, 11, 14), Time::from_hour(22), vec![1, 2])]
+        );
+        let (d, t, _) = agg.iter().next().unwrap();
+        assert_eq!(UnixTime::from((d, t)), UnixTime::from_secs(1_699_999_200));
+        assert_eq!(
+            agg.push_timestamp(UnixTime::from_millis(i64::MAX), vec![3]),
+            Err(PushError::OutOfRange)
+        );
+        assert_eq!(agg.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_weeks() -> Result<(), PushError> {
+        let mut agg = Compactor::new()
+            .keep_for_days(2, Resolution::Day)
+            .keep_for_days(21, Resolution::Week)
+            .build()
+            .unwrap();
+        // 2024-03-04 is a Monday
+        for d in 1..=20 {
+            agg.push(date(2024, 3, d), time(12, 0, 0), vec![d])?;
+        }
+        assert_eq!(
+            agg.data.0,
+            vec![
+                (date(2024, 2, 26), Time::WHOLE_WEEK, vec![1, 2, 3]),
+                (date(2024, 3, 4), Time::WHOLE_WEEK, (4..=10).collect()),
+                (date(2024, 3, 11), Time::WHOLE_WEEK, (11..=17).collect()),
+                // This week isn't over yet, so it's left alone
+                (date(2024, 3, 18), Time::WHOLE_DAY, vec![18]),
+                (date(2024, 3, 19), Time::WHOLE_DAY, vec![19]),
+                (date(2024, 3, 20), Time::WHOLE_DAY, vec![20]),
+            ]
+        );
+        assert_eq!(
+            agg.range_bounds((date(2024, 3, 10), Time::PM)..=(date(2024, 3, 18), Time::AM))
+                .count(),
+            3
+        );
+        // The week of 2024-02-26 is only discarded once all of it is too old
+        agg.update_date(date(2024, 3, 23));
+        assert_eq!(agg.data.0[0].0, date(2024, 2, 26));
+        agg.update_date(date(2024, 3, 24));
+        assert_eq!(agg.data.0[0].0, date(2024, 3, 4));
+        Ok(())
+    }
+
+    #[test]
+    fn test_months() -> Result<(), PushError> {
+        let mut agg = Compactor::new()
+            .keep_for_days(400, Resolution::Month)
+            .build()
+            .unwrap();
+        for m in 1..=3 {
+            agg.push(date(2024, m, 10), time(12, 0, 0), vec![m])?;
+            agg.push(date(2024, m, 20), time(12, 0, 0), vec![m])?;
+        }
+        assert_eq!(
+            agg.data.0,
+            vec![
+                (date(2024, 1, 1), Time::WHOLE_MONTH, vec![1, 1]),
+                (date(2024, 2, 1), Time::WHOLE_MONTH, vec![2, 2]),
+                (date(2024, 3, 1), Time::WHOLE_MONTH, vec![3, 3]),
+            ]
+        );
+        assert_eq!(
+            format!("{:?}", agg.data),
+            "{2024-01-01 whole month: [1, 1], 2024-02-01 whole month: [2, 2], \
+            2024-03-01 whole month: [3, 3]}"
+        );
+        let quarters = agg
+            .iter_with_max_resolution(Resolution::Quarter)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            quarters,
+            vec![(
+                date(2024, 1, 1),
+                Time::WHOLE_QUARTER,
+                vec![1, 1, 2, 2, 3, 3]
+            )]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_len_budget() -> Result<(), PushError> {
+        let mut agg = Compactor::new()
+            .keep_for_days(2, Resolution::Hour)
+            .keep_for_days(10, Resolution::Day)
+            .len_budget(30)
+            .build()
+            .unwrap();
+        for d in 1..=5 {
+            for h in 0..24 {
+                agg.push(date(2024, 3, d), time(h, 0, 0), vec![h])?;
+                assert!(agg.len() <= 30);
+            }
+        }
+        // The 4th was compacted early, and the 5th is left alone
+        assert_eq!(agg.len(), 4 + 24);
+        assert_eq!(
+            agg.iter().nth(3),
+            Some((date(2024, 3, 4), Time::WHOLE_DAY, &(0..24).collect()))
+        );
+        // Nothing has been discarded yet
+        assert_eq!(agg.iter().flat_map(|x| x.2).count(), 5 * 24);
+
+        // If the newest day doesn't fit, its oldest values are discarded
+        let mut agg = Compactor::new()
+            .keep_for_days(2, Resolution::Hour)
+            .len_budget(10)
+            .build()
+            .unwrap();
+        for h in 0..24 {
+            agg.push(date(2024, 3, 1), time(h, 0, 0), vec![h])?;
+        }
+        assert_eq!(agg.len(), 10);
+        assert_eq!(agg.iter().next().unwrap().2, &vec![14]);
+
+        // Updating the date enforces the budget too, eg. for data which was
+        // loaded over budget
+        agg.data.0 = (0..24)
+            .map(|h| (date(2024, 3, 1), Time::from_hour(h), vec![h]))
+            .collect();
+        agg.update_date(date(2024, 3, 2));
+        assert_eq!(agg.len(), 10);
+        assert_eq!(agg.iter().next().unwrap().2, &vec![14]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bytes() -> Result<(), PushError> {
+        let mut agg = Compactor::new()
+            .keep_for_days(2, Resolution::Minute)
+            .keep_for_days(4, Resolution::Hour)
+            .keep_for_days(60, Resolution::Week)
+            .build()
+            .unwrap();
+        for d in 1..=31 {
+            for h in 0..24 {
+                for m in [0, 20, 40] {
+                    agg.push(date(2024, 1, d), time(h, m, 0), vec![m as u32])?;
+                }
+            }
+        }
+        let bytes = agg.to_bytes();
+        assert_eq!(Compactor::from_bytes(&bytes).unwrap(), agg);
+        // Storing the dates and times naively would take 8 bytes per entry
+        let values = agg.iter().map(|(_, _, x)| 4 + 4 * x.len()).sum::<usize>();
+        let overhead = bytes.len() - values;
+        assert!(overhead < agg.len() * 4, "{overhead}");
+
+        // Corruption is detected
+        for i in 0..bytes.len() {
+            let mut bytes = bytes.clone();
+            bytes[i] ^= 0x10;
+            assert!(Compactor::<Vec<u32>>::from_bytes(&bytes).is_err(), "{i}");
+        }
+        for len in 0..bytes.len() {
+            assert!(Compactor::<Vec<u32>>::from_bytes(&bytes[..len]).is_err());
+        }
+        assert!(Compactor::<Vec<u32>>::from_bytes(b"CMPC\x02").is_err());
+
+        // So is data which doesn't satisfy the invariants, even if the
+        // checksum is good
+        let mut bad = agg.clone();
+        bad.data.0.swap(0, 1);
+        assert!(Compactor::<Vec<u32>>::from_bytes(&bad.to_bytes()).is_err());
+        let mut bad = agg.clone();
+        bad.data.0[0].0 = date(2024, 1, 2);
+        assert!(Compactor::<Vec<u32>>::from_bytes(&bad.to_bytes()).is_err());
+        let mut bad = agg.clone();
+        bad.data.0.last_mut().unwrap().1 = time(23, 40, 10);
+        assert!(Compactor::<Vec<u32>>::from_bytes(&bad.to_bytes()).is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_agg() {
         let mut agg = Compactor::new()
@@ -1,9 +1,17 @@
-use crate::{Aggregate, Date, Resolution, Time, policy::Policy};
+use crate::{
+    Aggregate, Date, Resolution, Time,
+    codec::{Codec, decode_varint, encode_varint, invalid_data},
+    policy::Policy,
+};
 use core::fmt;
+use std::{
+    collections::BTreeSet,
+    io,
+    ops::{Bound, RangeBounds},
+};
 
 #[derive(Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
-// TODO: RLE the dates?
 pub(crate) struct CompactedData<T>(pub(crate) Vec<(Date, Time, T)>);
 
 impl<T> Default for CompactedData<T> {
@@ -22,20 +30,164 @@ impl<T: fmt::Debug> fmt::Debug for CompactedData<T> {
     }
 }
 
+/// The dates whose entries have changed, so that a
+/// [`Store`](crate::store::Store) only has to rewrite their segments.  The
+/// default doesn't record anything.
+#[derive(Default)]
+pub(crate) struct Dirty(Option<BTreeSet<Date>>);
+
+impl Dirty {
+    pub(crate) fn tracking() -> Self {
+        Dirty(Some(BTreeSet::new()))
+    }
+
+    pub(crate) fn mark(&mut self, date: Date) {
+        if let Some(dates) = &mut self.0 {
+            dates.insert(date);
+        }
+    }
+
+    fn mark_all<'a, T: 'a>(&mut self, entries: impl IntoIterator<Item = &'a (Date, Time, T)>) {
+        if let Some(dates) = &mut self.0 {
+            dates.extend(entries.into_iter().map(|x| x.0));
+        }
+    }
+
+    pub(crate) fn dates(&self) -> impl Iterator<Item = Date> + '_ {
+        self.0.iter().flatten().copied()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        if let Some(dates) = &mut self.0 {
+            dates.clear();
+        }
+    }
+}
+
+impl<T> CompactedData<T> {
+    /// The entries which overlap the given range.  The bounds are intervals
+    /// too: an included bound covers the whole of its interval, and an
+    /// excluded bound none of it.
+    pub(crate) fn range(&self, range: impl RangeBounds<(Date, Time)>) -> &[(Date, Time, T)] {
+        // Entries are disjoint and sorted, so both their first and last
+        // millis are monotonic
+        let first = |(d, t, _): &(Date, Time, T)| first_milli(*d, *t);
+        let last = |(d, t, _): &(Date, Time, T)| last_milli(*d, *t);
+        let lo = match range.start_bound() {
+            Bound::Included((d, t)) => self.0.partition_point(|x| last(x) < first_milli(*d, *t)),
+            Bound::Excluded((d, t)) => self.0.partition_point(|x| last(x) <= last_milli(*d, *t)),
+            Bound::Unbounded => 0,
+        };
+        let hi = match range.end_bound() {
+            Bound::Included((d, t)) => self.0.partition_point(|x| first(x) <= last_milli(*d, *t)),
+            Bound::Excluded((d, t)) => self.0.partition_point(|x| first(x) < first_milli(*d, *t)),
+            Bound::Unbounded => self.0.len(),
+        };
+        &self.0[lo..hi.max(lo)]
+    }
+}
+
+/// Entries are grouped into runs with the same date, so each date is only
+/// stored once.  Within a run, each `Time` is stored as the difference between
+/// its bits and those of the previous one.  Since times are sorted, and
+/// neighbouring entries usually have the same resolution, this is usually a
+/// small number shifted left: we store the number and the shift.
+impl<T: Codec> Codec for CompactedData<T> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let runs = self.0.chunk_by(|x, y| x.0 == y.0);
+        encode_varint(runs.clone().count() as u64, buf);
+        for run in runs {
+            run[0].0.encode(buf);
+            encode_varint(run.len() as u64, buf);
+            let mut prev = 0_u32;
+            for (_, time, x) in run {
+                let bits = time.to_bits();
+                let delta = bits.wrapping_sub(prev) as u64;
+                let shift = delta.trailing_zeros().min(32) as u64;
+                encode_varint((delta >> shift) << 6 | shift, buf);
+                prev = bits;
+                x.encode(buf);
+            }
+        }
+    }
+
+    fn decode(buf: &mut &[u8]) -> io::Result<Self> {
+        let mut entries: Vec<(Date, Time, T)> = vec![];
+        for _ in 0..decode_varint(buf)? {
+            let date = Date::decode(buf)?;
+            let mut prev = 0_u32;
+            for _ in 0..decode_varint(buf)? {
+                let x = decode_varint(buf)?;
+                let (delta, shift) = (x >> 6, (x & 63) as u32);
+                let delta = u32::try_from(delta)
+                    .ok()
+                    .filter(|_| shift <= 32)
+                    .and_then(|delta| (delta as u64).checked_shl(shift))
+                    .and_then(|delta| u32::try_from(delta).ok())
+                    .ok_or_else(|| invalid_data("Bad time"))?;
+                prev = prev.wrapping_add(delta);
+                let time = Time::try_from_bits(prev).ok_or_else(|| invalid_data("Bad time"))?;
+                entries.push((date, time, T::decode(buf)?));
+            }
+        }
+        let data = CompactedData(entries);
+        data.check(Resolution::Millisecond)?;
+        Ok(data)
+    }
+}
+
+impl<T> CompactedData<T> {
+    /// Check the invariants on data which came from outside: nothing is finer
+    /// than `max_res`, buckets coarser than a day start at the start of their
+    /// period, and entries are sorted and disjoint
+    pub(crate) fn check(&self, max_res: Resolution) -> io::Result<()> {
+        let mut prev: Option<(Date, Time)> = None;
+        for (date, time, _) in &self.0 {
+            if time.resolution() > max_res {
+                return Err(invalid_data("Data is finer than the policy allows"));
+            }
+            let mut start = *date;
+            start.reduce_to(time.resolution());
+            if start != *date {
+                return Err(invalid_data("Misaligned bucket"));
+            }
+            if let Some((d, t)) = prev
+                && last_milli(d, t) >= first_milli(*date, *time)
+            {
+                return Err(invalid_data("Entries out of order"));
+            }
+            prev = Some((*date, *time));
+        }
+        Ok(())
+    }
+}
+
 impl<T: Aggregate> CompactedData<T> {
-    /// Remove data on days up to and including `up_to`
-    fn discard(&mut self, up_to: Date) {
+    /// Remove data on days up to and including `up_to`.  Entries coarser than
+    /// a day are only removed once their whole period is in range.
+    fn discard(&mut self, up_to: Date, dirty: &mut Dirty) {
         let remove = self
             .0
             .iter()
-            .position(|x| x.0 > up_to)
+            .position(|x| x.0.period_end(x.1.resolution()) > up_to)
             .unwrap_or(self.0.len());
+        dirty.mark_all(&self.0[..remove]);
         self.0.drain(..remove);
     }
 
     /// Compact data on days up to and including `up_to`, reducing the
-    /// resolution to (at most) `res`
-    fn compact(&mut self, up_to: Date, res: Resolution) {
+    /// resolution to (at most) `res`.  If `res` is coarser than a day, only
+    /// whole periods are compacted.
+    fn compact(&mut self, up_to: Date, res: Resolution, dirty: &mut Dirty) {
+        let up_to = if res < Resolution::Day {
+            // The period containing `up_to` ends before the one containing
+            // the next day starts
+            let mut next = Date::from_civil(up_to.to_civil().tomorrow().unwrap());
+            next.reduce_to(res);
+            Date::from_civil(next.to_civil().yesterday().unwrap())
+        } else {
+            up_to
+        };
         let mut start = None;
         let mut end = None;
         for (i, x) in self.0.iter().enumerate() {
@@ -53,7 +205,9 @@ impl<T: Aggregate> CompactedData<T> {
         let Some((start, end)) = start.zip(end) else {
             return;
         };
+        dirty.mark_all(&self.0[start..=end]);
         let merged = with_max_res(res, self.0.splice(start..=end, [])).collect::<Vec<_>>();
+        dirty.mark_all(&merged);
         self.0.splice(start..start, merged);
 
         // Sanity check:
@@ -64,45 +218,76 @@ impl<T: Aggregate> CompactedData<T> {
         }
     }
 
+    /// Compact the oldest data ahead of schedule, until the policy's length
+    /// budget is met.  If nothing can be compacted any further, discard the
+    /// oldest data instead.  Data on the newest date is never compacted,
+    /// since pushes expect it to be at the policy's maximum resolution.
+    pub(crate) fn enforce_len_budget(&mut self, policy: &Policy, dirty: &mut Dirty) {
+        let Some(max_len) = policy.len_budget else {
+            return;
+        };
+        while self.0.len() > max_len {
+            let newest = self.0.last().unwrap().0;
+            let next = self.0.iter().find_map(|(date, time, _)| {
+                let res = policy
+                    .resolutions()
+                    .filter(|res| *res < time.resolution())
+                    .max()?;
+                let up_to = date.period_end(res);
+                (up_to < newest).then_some((up_to, res))
+            });
+            match next {
+                Some((up_to, res)) => self.compact(up_to, res, dirty),
+                None => {
+                    dirty.mark(self.0.remove(0).0);
+                }
+            }
+        }
+    }
+
     // TODO: The compactions could be combined... but it doesn't matter: this
     // isn't the fast path
-    pub(crate) fn apply_policy(&mut self, policy: &Policy, date: Date) {
-        let date = jiff::civil::date(date.year, date.month, date.day);
+    pub(crate) fn apply_policy(&mut self, policy: &Policy, date: Date, dirty: &mut Dirty) {
+        let date = date.to_civil();
 
         // Remove data no longer covered by any policy
         let up_to = date - jiff::Span::new().days(policy.max_retention);
-        let up_to = Date {
-            year: up_to.year(),
-            month: up_to.month(),
-            day: up_to.day(),
-        };
-        self.discard(up_to);
+        self.discard(Date::from_civil(up_to), dirty);
 
         for (days, res) in &policy.compaction_rules {
             let up_to = date - jiff::Span::new().days(*days);
-            let up_to = Date {
-                year: up_to.year(),
-                month: up_to.month(),
-                day: up_to.day(),
-            };
-            self.compact(up_to, *res);
+            self.compact(Date::from_civil(up_to), *res, dirty);
         }
     }
 }
 
+/// The first millisecond of the bucket
+pub(crate) fn first_milli(date: Date, time: Time) -> (Date, Time) {
+    (date, time.first_milli())
+}
+
+/// The last millisecond of the bucket, which may be on a later day
+pub(crate) fn last_milli(date: Date, time: Time) -> (Date, Time) {
+    (date.period_end(time.resolution()), time.last_milli())
+}
+
 pub(crate) fn with_max_res<T: Aggregate>(
     res: Resolution,
     xs: impl Iterator<Item = (Date, Time, T)>,
 ) -> impl Iterator<Item = (Date, Time, T)> {
     let mut cur: Option<(Date, Time, T)> = None;
     xs.map(Some).chain([None]).filter_map(move |x| match x {
-        Some((date, mut time, x)) => {
-            time.reduce_to(res);
-            if let Some(cur) = &mut cur {
-                if cur.0 == date && cur.1 == time {
-                    cur.2.merge(x);
-                    return None;
-                }
+        Some((mut date, mut time, x)) => {
+            if res < time.resolution() {
+                date.reduce_to(res);
+                time.reduce_to(res);
+            }
+            if let Some(cur) = &mut cur
+                && cur.0 == date
+                && cur.1 == time
+            {
+                cur.2.merge(x);
+                return None;
             }
             cur.replace((date, time, x))
         }
@@ -1,3 +1,4 @@
+use super::Resolution;
 use core::fmt;
 
 /// Just a date
@@ -11,6 +12,65 @@ pub struct Date {
     pub day: i8,
 }
 
+impl Date {
+    /// Move to the first day of the period at resolution `res` containing this
+    /// date.  Weeks start on Monday.  Has no effect if `res` is `Day` or finer.
+    ///
+    /// ```
+    /// # use compactor::{Date, Resolution};
+    /// let mut date = Date { year: 2024, month: 8, day: 15 };
+    /// date.reduce_to(Resolution::Week);
+    /// assert_eq!(date.to_string(), "2024-08-12");
+    /// date.reduce_to(Resolution::Quarter);
+    /// assert_eq!(date.to_string(), "2024-07-01");
+    /// ```
+    pub fn reduce_to(&mut self, res: Resolution) {
+        match res {
+            Resolution::Year => {
+                self.month = 1;
+                self.day = 1;
+            }
+            Resolution::Quarter => {
+                self.month = (self.month - 1) / 3 * 3 + 1;
+                self.day = 1;
+            }
+            Resolution::Month => self.day = 1,
+            Resolution::Week => {
+                let date = self.to_civil();
+                let offset = date.weekday().to_monday_zero_offset();
+                *self = Date::from_civil(date - jiff::Span::new().days(offset));
+            }
+            _ => (),
+        }
+    }
+
+    /// The last day of the period at resolution `res` starting on this date.
+    /// This is just `self` if `res` is `Day` or finer.
+    pub(crate) fn period_end(self, res: Resolution) -> Date {
+        let date = self.to_civil();
+        let end = match res {
+            Resolution::Year => jiff::civil::date(self.year, 12, 31),
+            Resolution::Quarter => (date + jiff::Span::new().months(2)).last_of_month(),
+            Resolution::Month => date.last_of_month(),
+            Resolution::Week => date + jiff::Span::new().days(6),
+            _ => return self,
+        };
+        Date::from_civil(end)
+    }
+
+    pub(crate) fn to_civil(self) -> jiff::civil::Date {
+        jiff::civil::date(self.year, self.month, self.day)
+    }
+
+    pub(crate) fn from_civil(date: jiff::civil::Date) -> Date {
+        Date {
+            year: date.year(),
+            month: date.month(),
+            day: date.day(),
+        }
+    }
+}
+
 impl fmt::Display for Date {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
@@ -54,3 +114,37 @@ impl From<Date> for chrono::NaiveDate {
             .unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i16, month: i8, day: i8) -> Date {
+        Date { year, month, day }
+    }
+
+    #[test]
+    fn test_periods() {
+        let cases = [
+            (Resolution::Week, date(2024, 2, 26), date(2024, 3, 3)),
+            (Resolution::Month, date(2024, 2, 1), date(2024, 2, 29)),
+            (Resolution::Quarter, date(2024, 1, 1), date(2024, 3, 31)),
+            (Resolution::Year, date(2024, 1, 1), date(2024, 12, 31)),
+            (Resolution::Day, date(2024, 2, 29), date(2024, 2, 29)),
+            (Resolution::Hour, date(2024, 2, 29), date(2024, 2, 29)),
+        ];
+        for (res, start, end) in cases {
+            let mut x = date(2024, 2, 29);
+            x.reduce_to(res);
+            assert_eq!(x, start, "{res}");
+            assert_eq!(x.period_end(res), end, "{res}");
+        }
+        let mut x = date(2024, 12, 31);
+        x.reduce_to(Resolution::Week);
+        assert_eq!(x, date(2024, 12, 30));
+        assert_eq!(x.period_end(Resolution::Week), date(2025, 1, 5));
+        let mut x = date(2024, 11, 30);
+        x.reduce_to(Resolution::Quarter);
+        assert_eq!(x.period_end(Resolution::Quarter), date(2024, 12, 31));
+    }
+}
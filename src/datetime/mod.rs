@@ -2,8 +2,10 @@ mod date;
 mod resolution;
 mod time;
 mod types;
+mod unix;
 
 pub use self::date::Date;
 pub use self::resolution::Resolution;
 pub use self::time::Time;
 pub use self::types::{AmPm, SixHour};
+pub use self::unix::{OutOfRange, UnixTime};
@@ -1,14 +1,16 @@
+use crate::policy::PolicyError;
 use core::fmt;
-use linearize::{Linearize, LinearizeExt};
-use std::{ops::Div, time::Duration};
+use linearize::Linearize;
+use std::{ops::Div, str::FromStr, time::Duration};
 
-/// There are 19 resolutions available:
+/// There are 23 resolutions available:
 ///
 /// * milli, 5ms, 10ms, 50ms, 100ms, 500ms
 /// * second, 5s, 15s, 30s
 /// * minute, 5m, 15m, 30m
 /// * hour, 3h, 6h, 12h (am/pm)
 /// * whole day
+/// * week, month, quarter, year
 ///
 /// The `Ord` impl follows natural-language: `x < y` means that x is
 /// lower-resolution than y.
@@ -26,7 +28,14 @@ use std::{ops::Div, time::Duration};
 /// # use compactor::Resolution;
 /// assert_eq!(Resolution::ThirtySecond / Resolution::FiftyMilli, 600);
 /// ```
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Linearize)]
+///
+/// The resolutions coarser than a day are the exception.  Weeks (which start
+/// on Monday) don't line up with months, so a policy can't use `Week` together
+/// with `Month`, `Quarter`, or `Year`.
+// These were added after the others, so they come last: the variant index is
+// what serde and `Linearize` use, and it has to stay the same.  `Ord` is
+// written by hand.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Linearize)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Resolution {
     Day,
@@ -48,11 +57,72 @@ pub enum Resolution {
     TenMilli,
     FiveMilli,
     Millisecond,
+    Week,
+    Month,
+    Quarter,
+    Year,
+}
+
+impl Resolution {
+    /// Every resolution, from coarsest to finest
+    const ALL: [Resolution; 23] = [
+        Resolution::Year,
+        Resolution::Quarter,
+        Resolution::Month,
+        Resolution::Week,
+        Resolution::Day,
+        Resolution::AmPm,
+        Resolution::SixHour,
+        Resolution::ThreeHour,
+        Resolution::Hour,
+        Resolution::ThirtyMinute,
+        Resolution::FifteenMinute,
+        Resolution::FiveMinute,
+        Resolution::Minute,
+        Resolution::ThirtySecond,
+        Resolution::FifteenSecond,
+        Resolution::FiveSecond,
+        Resolution::Second,
+        Resolution::FiveHundredMilli,
+        Resolution::HundredMilli,
+        Resolution::FiftyMilli,
+        Resolution::TenMilli,
+        Resolution::FiveMilli,
+        Resolution::Millisecond,
+    ];
+
+    /// The position in [`Resolution::ALL`]
+    const fn rank(self) -> usize {
+        match self {
+            Resolution::Year => 0,
+            Resolution::Quarter => 1,
+            Resolution::Month => 2,
+            Resolution::Week => 3,
+            // The rest are declared in order
+            res => res as usize + 4,
+        }
+    }
+}
+
+impl PartialOrd for Resolution {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Resolution {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.rank().cmp(&other.rank())
+    }
 }
 
 impl fmt::Display for Resolution {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Resolution::Year => f.write_str("year"),
+            Resolution::Quarter => f.write_str("quarter"),
+            Resolution::Month => f.write_str("month"),
+            Resolution::Week => f.write_str("week"),
             Resolution::Day => f.write_str("day"),
             Resolution::AmPm => f.write_str("AM/PM"),
             Resolution::SixHour => f.write_str("6h"),
@@ -76,9 +146,59 @@ impl fmt::Display for Resolution {
     }
 }
 
+/// Accepts the `Display` form, as well as abbreviations like "1m" or "h".
+/// Case-insensitive.
+///
+/// ```
+/// # use compactor::Resolution;
+/// assert_eq!("AM/PM".parse(), Ok(Resolution::AmPm));
+/// assert_eq!("1m".parse(), Ok(Resolution::Minute));
+/// assert_eq!("15s".parse(), Ok(Resolution::FifteenSecond));
+/// ```
+impl FromStr for Resolution {
+    type Err = PolicyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim().to_ascii_lowercase().as_str() {
+            "year" | "y" | "1y" => Resolution::Year,
+            "quarter" | "q" | "1q" => Resolution::Quarter,
+            "month" | "mo" | "1mo" => Resolution::Month,
+            "week" | "w" | "1w" => Resolution::Week,
+            "day" | "d" | "1d" => Resolution::Day,
+            "am/pm" | "ampm" | "12h" => Resolution::AmPm,
+            "6h" => Resolution::SixHour,
+            "3h" => Resolution::ThreeHour,
+            "hour" | "h" | "1h" => Resolution::Hour,
+            "30m" => Resolution::ThirtyMinute,
+            "15m" => Resolution::FifteenMinute,
+            "5m" => Resolution::FiveMinute,
+            "minute" | "min" | "m" | "1m" => Resolution::Minute,
+            "30s" => Resolution::ThirtySecond,
+            "15s" => Resolution::FifteenSecond,
+            "5s" => Resolution::FiveSecond,
+            "second" | "sec" | "s" | "1s" => Resolution::Second,
+            "500ms" => Resolution::FiveHundredMilli,
+            "100ms" => Resolution::HundredMilli,
+            "50ms" => Resolution::FiftyMilli,
+            "10ms" => Resolution::TenMilli,
+            "5ms" => Resolution::FiveMilli,
+            "millisecond" | "milli" | "ms" | "1ms" => Resolution::Millisecond,
+            _ => return Err(PolicyError::UnknownResolution(s.trim().to_string())),
+        })
+    }
+}
+
+const AVERAGE_YEAR_SECS: u64 = 365 * 24 * 60 * 60 + 5 * 60 * 60 + 49 * 60 + 12;
+
 impl Resolution {
+    /// Months, quarters, and years vary in length, so for these you get the
+    /// average length in the Gregorian calendar.
     pub const fn width(self) -> std::time::Duration {
         match self {
+            Resolution::Year => Duration::from_secs(AVERAGE_YEAR_SECS),
+            Resolution::Quarter => Duration::from_secs(AVERAGE_YEAR_SECS / 4),
+            Resolution::Month => Duration::from_secs(AVERAGE_YEAR_SECS / 12),
+            Resolution::Week => Duration::from_secs(7 * 24 * 60 * 60),
             Resolution::Day => Duration::from_secs(24 * 60 * 60),
             Resolution::AmPm => Duration::from_secs(12 * 60 * 60),
             Resolution::SixHour => Duration::from_secs(6 * 60 * 60),
@@ -110,11 +230,11 @@ impl From<Resolution> for std::time::Duration {
 
 impl Resolution {
     pub fn coarser(self) -> Option<Self> {
-        Resolution::from_linear(self.linearize().checked_sub(1)?)
+        Resolution::ALL.get(self.rank().checked_sub(1)?).copied()
     }
 
     pub fn finer(self) -> Option<Self> {
-        Resolution::from_linear(self.linearize().checked_add(1)?)
+        Resolution::ALL.get(self.rank() + 1).copied()
     }
 
     /// `from` is inclusive, `to` is exclusive.  `from` should be finer than
@@ -123,20 +243,33 @@ impl Resolution {
         from: Resolution,
         to: Resolution,
     ) -> impl DoubleEndedIterator<Item = Resolution> {
-        let from = from.linearize();
-        let to = to.linearize();
-        Resolution::variants()
+        let (from, to) = (from.rank(), to.rank());
+        Resolution::ALL
+            .into_iter()
             .skip(to + 1)
             .take(from.saturating_sub(to))
             .rev()
     }
 }
 
+/// Panics if `self` isn't a whole number of `rhs`, which can happen when
+/// `self` is a month, quarter, or year.
 #[allow(clippy::suspicious_arithmetic_impl)]
 impl Div for Resolution {
     type Output = u32;
 
     fn div(self, rhs: Self) -> Self::Output {
+        match (self, rhs) {
+            (x, y) if x == y => return 1,
+            (Resolution::Week, rhs) if rhs >= Resolution::Day => {
+                return 7 * (Resolution::Day / rhs);
+            }
+            (Resolution::Year, Resolution::Quarter) => return 4,
+            (Resolution::Year, Resolution::Month) => return 12,
+            (Resolution::Quarter, Resolution::Month) => return 3,
+            (x, y) if x < Resolution::Day => panic!("A {x} isn't a whole number of {y}s"),
+            _ => (),
+        }
         let mut ret = 1;
         for res in Resolution::range(rhs, self) {
             ret *= res.subdivision() as u32;
@@ -148,6 +281,7 @@ impl Div for Resolution {
 impl Resolution {
     pub(crate) fn subdivision(self) -> u8 {
         match self {
+            Resolution::Year | Resolution::Quarter | Resolution::Month | Resolution::Week => 0,
             Resolution::Day => 0,
             Resolution::AmPm => 2,
             Resolution::SixHour => 2,
@@ -172,6 +306,7 @@ impl Resolution {
 
     pub(crate) fn n_bits(self) -> u8 {
         match self {
+            Resolution::Year | Resolution::Quarter | Resolution::Month | Resolution::Week => 0,
             Resolution::Day => 0,
             Resolution::AmPm => 1,
             Resolution::SixHour => 1,
@@ -194,8 +329,11 @@ impl Resolution {
         }
     }
 
+    /// The resolutions coarser than a day aren't part of `Time`'s tree, so they
+    /// get the same answer as `Day`
     pub(crate) fn trailing_zeros(self) -> u8 {
         match self {
+            Resolution::Year | Resolution::Quarter | Resolution::Month | Resolution::Week => 31,
             Resolution::Day => 31,
             Resolution::AmPm => 30,
             Resolution::SixHour => 29,
@@ -247,17 +385,45 @@ impl Resolution {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use linearize::LinearizeExt;
+
+    /// The resolutions which make up `Time`'s tree
+    fn intra_day() -> impl DoubleEndedIterator<Item = Resolution> {
+        Resolution::variants().filter(|res| *res >= Resolution::Day)
+    }
 
     #[test]
     fn test_x_in_y() {
         assert_eq!(Resolution::Minute / Resolution::Second, 60);
         assert_eq!(Resolution::Hour / Resolution::Minute, 60);
         assert_eq!(Resolution::Day / Resolution::Hour, 24);
+        assert_eq!(Resolution::Week / Resolution::Hour, 7 * 24);
+        assert_eq!(Resolution::Year / Resolution::Month, 12);
+        assert_eq!(Resolution::Month / Resolution::Month, 1);
     }
 
     #[test]
-    fn test_enough_bits() {
+    fn test_from_str() {
         for res in Resolution::variants() {
+            assert_eq!(res.to_string().parse(), Ok(res));
+        }
+        assert_eq!(" Hour ".parse(), Ok(Resolution::Hour));
+        assert_eq!("1mo".parse(), Ok(Resolution::Month));
+        assert_eq!(
+            "10m".parse::<Resolution>(),
+            Err(PolicyError::UnknownResolution("10m".to_string()))
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_month_in_days() {
+        let _ = Resolution::Month / Resolution::Day;
+    }
+
+    #[test]
+    fn test_enough_bits() {
+        for res in intra_day() {
             let has = res.n_bits() as u32;
             let required = if res.subdivision() == 0 {
                 0
@@ -284,6 +450,21 @@ mod tests {
         assert_eq!(mask(Resolution::ThirtySecond), 0b10000000_000000000000);
     }
 
+    #[test]
+    fn test_order() {
+        // The variant indices (used by serde and `Linearize`) are stable
+        assert_eq!(Resolution::Day.linearize(), 0);
+        assert_eq!(Resolution::Millisecond.linearize(), 18);
+        assert_eq!(Resolution::Year.linearize(), 22);
+        let mut all: Vec<_> = Resolution::variants().collect();
+        all.sort();
+        assert_eq!(all, Resolution::ALL);
+        assert_eq!(Resolution::Day.coarser(), Some(Resolution::Week));
+        assert_eq!(Resolution::Week.finer(), Some(Resolution::Day));
+        assert_eq!(Resolution::Year.coarser(), None);
+        assert_eq!(Resolution::Millisecond.finer(), None);
+    }
+
     #[test]
     fn test_range() {
         assert_eq!(
@@ -299,25 +480,26 @@ mod tests {
 
     #[test]
     fn test_trailing_zeros() {
-        for res in Resolution::variants() {
+        for res in intra_day() {
             assert_eq!(Resolution::from_trailing_zeros(res.trailing_zeros()), res)
         }
     }
 
     #[test]
     fn test_n_bits() {
-        for res in Resolution::variants() {
-            let n_bits = res.coarser().map_or(31, |x| x.trailing_zeros()) - res.trailing_zeros();
+        for res in intra_day() {
+            let n_bits = res
+                .coarser()
+                .filter(|x| *x >= Resolution::Day)
+                .map_or(31, |x| x.trailing_zeros())
+                - res.trailing_zeros();
             assert_eq!(res.n_bits(), n_bits, "{res:?}",)
         }
     }
 
     #[test]
     fn test_width() {
-        for (res1, res2) in Resolution::variants()
-            .rev()
-            .zip(Resolution::variants().rev().skip(1))
-        {
+        for (res1, res2) in intra_day().rev().zip(intra_day().rev().skip(1)) {
             assert_eq!(
                 res1.width() * res1.subdivision() as u32,
                 res2.width(),
@@ -325,4 +507,12 @@ mod tests {
             )
         }
     }
+
+    #[test]
+    fn test_coarse_width() {
+        assert_eq!(Resolution::Week.width(), Resolution::Day.width() * 7);
+        let year = Resolution::Year.width().as_secs_f64() / Resolution::Day.width().as_secs_f64();
+        assert_eq!(year, 365.2425);
+        assert_eq!(Resolution::Month.width() * 12, Resolution::Year.width());
+    }
 }
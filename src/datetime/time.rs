@@ -1,5 +1,5 @@
 use super::{AmPm, Resolution, SixHour};
-use std::{fmt, num::NonZero};
+use std::{fmt, num::NonZero, time::Duration};
 
 /// A time with a resolution
 ///
@@ -71,13 +71,29 @@ use std::{fmt, num::NonZero};
 /// A bonus: since there's always a one-bit somewhere, the "all-zeroes"
 /// bit pattern is invalid, and can be used to represent the `None` case of
 /// `Option<Time>`.
+///
+/// ## Daylight saving time
+///
+/// `Time` is a civil (wall-clock) time, and the tree always spans exactly 24
+/// hours.  On a 23-hour day, the bit patterns inside the skipped hour are
+/// never used.  On a 25-hour day, the bit patterns inside the repeated hour
+/// are used twice.  See `ZonedCompactor` for how this plays out in practice.
+///
+/// ## Coarser than a day
+///
+/// A few bit patterns are invalid as positions in the tree (they have an hour
+/// marker, but an hour-of-three-hours of 3).  We use these to represent
+/// [`Time::WHOLE_WEEK`], [`Time::WHOLE_MONTH`], [`Time::WHOLE_QUARTER`], and
+/// [`Time::WHOLE_YEAR`].  These cover the whole day and then some: the rest
+/// of the period is determined by the accompanying [`Date`](super::Date),
+/// which should be the first day of the period.
 #[derive(Copy, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Time(NonZero<u32>);
 
 impl PartialOrd for Time {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        if self.0.trailing_zeros() == other.0.trailing_zeros() {
+        if self.resolution() == other.resolution() {
             Some(self.0.cmp(&other.0))
         } else {
             None
@@ -169,28 +185,39 @@ impl From<Time> for chrono::NaiveTime {
 
 impl Time {
     pub fn resolution(self) -> Resolution {
-        Resolution::from_trailing_zeros(self.0.trailing_zeros() as u8)
+        match self {
+            Time::WHOLE_WEEK => Resolution::Week,
+            Time::WHOLE_MONTH => Resolution::Month,
+            Time::WHOLE_QUARTER => Resolution::Quarter,
+            Time::WHOLE_YEAR => Resolution::Year,
+            _ => Resolution::from_trailing_zeros(self.0.trailing_zeros() as u8),
+        }
     }
 
     /// Has no effect if `res` is higher than the current resolution
+    ///
+    /// If `res` is coarser than `Day`, the date needs reducing too: see
+    /// [`Date::reduce_to`](super::Date::reduce_to).
     pub fn reduce_to(&mut self, res: Resolution) {
         if res >= self.resolution() {
             return;
         }
-        let mut x = self.0.get();
-        x &= u32::MAX << res.trailing_zeros();
-        x |= 1 << res.trailing_zeros();
-        self.0 = NonZero::new(x).unwrap();
+        *self = match res {
+            Resolution::Year => Time::WHOLE_YEAR,
+            Resolution::Quarter => Time::WHOLE_QUARTER,
+            Resolution::Month => Time::WHOLE_MONTH,
+            Resolution::Week => Time::WHOLE_WEEK,
+            _ => Time::from_bits(self.0.get(), res),
+        };
     }
 
     pub fn with_res(self, res: Resolution) -> Option<Self> {
         if res > self.resolution() {
             return None;
         }
-        let mut x = self.0.get();
-        x &= u32::MAX << res.trailing_zeros();
-        x |= 1 << res.trailing_zeros();
-        Some(Time(NonZero::new(x).unwrap()))
+        let mut x = self;
+        x.reduce_to(res);
+        Some(x)
     }
 
     /// Expects the data bits to be in their correct positions, but for there to
@@ -198,7 +225,49 @@ impl Time {
     fn from_bits(mut x: u32, res: Resolution) -> Self {
         x &= u32::MAX << res.trailing_zeros();
         x |= 1 << res.trailing_zeros();
-        Time(NonZero::new(x).unwrap())
+        let t = Time(NonZero::new(x).unwrap());
+        // The coarse tags have the same marker as hours, so an hour with a bad
+        // hour-of-three-hours would be mistaken for one
+        debug_assert_eq!(t.resolution(), res, "{x:#b}");
+        t
+    }
+
+    pub(crate) fn to_bits(self) -> u32 {
+        self.0.get()
+    }
+
+    /// The inverse of `to_bits()`.  Returns `None` if `x` isn't a valid bit
+    /// pattern.
+    pub(crate) fn try_from_bits(x: u32) -> Option<Self> {
+        let t = Time(NonZero::new(x)?);
+        let tags = [
+            Time::WHOLE_WEEK,
+            Time::WHOLE_MONTH,
+            Time::WHOLE_QUARTER,
+            Time::WHOLE_YEAR,
+        ];
+        if tags.contains(&t) {
+            return Some(t);
+        }
+        let tz = x.trailing_zeros() as u8;
+        let res = Resolution::range(Resolution::Millisecond, Resolution::Week)
+            .find(|res| res.trailing_zeros() == tz)?;
+        for res in Resolution::range(res, Resolution::Day) {
+            let field = (x >> (res.trailing_zeros() + 1)) & !(u32::MAX << res.n_bits());
+            if field >= res.subdivision() as u32 {
+                return None;
+            }
+        }
+        Some(t)
+    }
+
+    /// Times coarser than a day cover the whole of each day
+    fn max_day(self) -> Time {
+        if self.resolution() < Resolution::Day {
+            Time::WHOLE_DAY
+        } else {
+            self
+        }
     }
 
     /// Compare two values by first coarsening them to the lower of their two
@@ -206,6 +275,9 @@ impl Time {
     /// not `eq()`.  This function will return Ordering::Eq when one value is
     /// inside the other, whereas `eq()` would return `false`.
     pub fn coarse_cmp(self, other: Time) -> std::cmp::Ordering {
+        if self.resolution() <= Resolution::Day || other.resolution() <= Resolution::Day {
+            return std::cmp::Ordering::Equal;
+        }
         let zeroes = self.0.trailing_zeros().max(other.0.trailing_zeros());
         let mut x = self.0.get();
         x &= u32::MAX << zeroes;
@@ -216,13 +288,192 @@ impl Time {
         x.cmp(&y)
     }
 
-    // pub fn start(self) -> jiff::civil::Time {
-    //     todo!()
-    // }
+    /// The start of this interval, as a number of milliseconds since midnight
+    /// (inclusive).  Times coarser than a day start at midnight.
+    pub fn start_millis(self) -> u32 {
+        let secs = self.hour() as u32 * 60 * 60 + self.minute() as u32 * 60 + self.second() as u32;
+        secs * 1000 + self.millis() as u32
+    }
+
+    /// The first millisecond inside this interval (on the first day, if the
+    /// interval is longer than a day)
+    pub(crate) fn first_milli(self) -> Time {
+        let this = self.max_day();
+        let x = this.0.get() & !(1 << this.0.trailing_zeros());
+        Time::from_bits(x, Resolution::Millisecond)
+    }
+
+    /// The last millisecond inside this interval (on the last day, if the
+    /// interval is longer than a day)
+    pub(crate) fn last_milli(self) -> Time {
+        let this = self.max_day();
+        let mut x = this.0.get() & !(1 << this.0.trailing_zeros());
+        for res in Resolution::range(Resolution::Millisecond, this.resolution()) {
+            let mut max = res.subdivision() as u32 - 1;
+            set_res_bits(&mut x, res, &mut max);
+        }
+        Time::from_bits(x, Resolution::Millisecond)
+    }
+
+    /// The next interval at the same resolution, or `None` if this is the
+    /// last one in the day
+    pub(crate) fn succ(self) -> Option<Time> {
+        if self.resolution() <= Resolution::Day {
+            // Don't mistake a coarse tag for an hour
+            return None;
+        }
+        let mut bits = self.0.get() & !(1 << self.0.trailing_zeros());
+        // Increment the finest field, carrying into the coarser ones
+        for res in Resolution::range(self.resolution(), Resolution::Day) {
+            let field = (bits >> (res.trailing_zeros() + 1)) & !(u32::MAX << res.n_bits());
+            let mut x = field + 1;
+            if x < res.subdivision() as u32 {
+                set_res_bits(&mut bits, res, &mut x);
+                return Some(Time::from_bits(bits, self.resolution()));
+            }
+            set_res_bits(&mut bits, res, &mut 0);
+        }
+        None
+    }
+
+    /// The end of this interval, as a number of milliseconds since midnight
+    /// (exclusive).  Times coarser than a day end at the following midnight.
+    pub fn end_millis(self) -> u32 {
+        self.last_milli().start_millis() + 1
+    }
+
+    /// The start of this interval, as the time since midnight (inclusive)
+    ///
+    /// ```
+    /// # use compactor::Time;
+    /// # use std::time::Duration;
+    /// let t = Time::from_hour(9).with_minute(30);
+    /// assert_eq!(t.start(), Duration::from_secs(9 * 3600 + 30 * 60));
+    /// assert_eq!(t.end(), Duration::from_secs(9 * 3600 + 31 * 60));
+    /// assert_eq!(Time::PM.end(), Duration::from_secs(24 * 3600));
+    /// ```
+    pub fn start(self) -> Duration {
+        Duration::from_millis(self.start_millis() as u64)
+    }
+
+    /// The end of this interval, as the time since midnight (exclusive)
+    pub fn end(self) -> Duration {
+        Duration::from_millis(self.end_millis() as u64)
+    }
+
+    /// The start of this interval (inclusive)
+    #[cfg(feature = "jiff")]
+    pub fn start_jiff(self) -> jiff::civil::Time {
+        jiff::civil::Time::MIN + jiff::SignedDuration::from_millis(self.start_millis() as i64)
+    }
+
+    /// The end of this interval (exclusive).  For intervals which run to the
+    /// end of the day, this wraps around to midnight.
+    #[cfg(feature = "jiff")]
+    pub fn end_jiff(self) -> jiff::civil::Time {
+        jiff::civil::Time::MIN
+            .wrapping_add(jiff::SignedDuration::from_millis(self.end_millis() as i64))
+    }
+
+    /// The start of this interval (inclusive)
+    #[cfg(feature = "chrono")]
+    pub fn start_chrono(self) -> chrono::NaiveTime {
+        chrono::NaiveTime::MIN + chrono::TimeDelta::milliseconds(self.start_millis() as i64)
+    }
+
+    /// The end of this interval (exclusive).  For intervals which run to the
+    /// end of the day, this wraps around to midnight.
+    #[cfg(feature = "chrono")]
+    pub fn end_chrono(self) -> chrono::NaiveTime {
+        chrono::NaiveTime::MIN + chrono::TimeDelta::milliseconds(self.end_millis() as i64)
+    }
+
+    /// Whether `other` is inside this interval (or the same as it).  Times
+    /// coarser than a day contain the whole day.  Months, quarters and years
+    /// nest, so they contain the finer ones among themselves, but weeks don't
+    /// line up with any of them.
+    ///
+    /// ```
+    /// # use compactor::Time;
+    /// let t = Time::from_hour(9);
+    /// assert!(Time::AM.contains(t));
+    /// assert!(t.contains(t.with_minute(15)));
+    /// assert!(!t.contains(Time::AM));
+    /// assert!(!Time::PM.contains(t));
+    ///
+    /// for t in [
+    ///     Time::WHOLE_DAY,
+    ///     Time::WHOLE_WEEK,
+    ///     Time::WHOLE_MONTH,
+    ///     Time::WHOLE_QUARTER,
+    ///     Time::WHOLE_YEAR,
+    /// ] {
+    ///     assert!(t.contains(t));
+    /// }
+    /// assert!(Time::WHOLE_YEAR.contains(Time::WHOLE_MONTH));
+    /// assert!(!Time::WHOLE_MONTH.contains(Time::WHOLE_YEAR));
+    /// assert!(!Time::WHOLE_YEAR.contains(Time::WHOLE_WEEK));
+    /// ```
+    pub fn contains(self, other: Time) -> bool {
+        if self.resolution() <= Resolution::Day {
+            return match (self.resolution(), other.resolution()) {
+                (_, x) if x >= Resolution::Day => true,
+                (Resolution::Week, _) | (_, Resolution::Week) => self == other,
+                (x, y) => x <= y,
+            };
+        }
+        other.with_res(self.resolution()) == Some(self)
+    }
+
+    /// Whether the two intervals have any time in common.  Within a day, the
+    /// intervals form a tree, so this means one of them contains the other.
+    ///
+    /// Times coarser than a day always overlap each other, even though a week
+    /// and a month don't contain one another: on a date they both cover, they
+    /// both cover the whole day.
+    ///
+    /// ```
+    /// # use compactor::Time;
+    /// assert!(Time::WHOLE_WEEK.overlaps(Time::WHOLE_MONTH));
+    /// assert!(!Time::WHOLE_WEEK.contains(Time::WHOLE_MONTH));
+    /// ```
+    pub fn overlaps(self, other: Time) -> bool {
+        if self.resolution() <= Resolution::Day && other.resolution() <= Resolution::Day {
+            return true;
+        }
+        self.contains(other) || other.contains(self)
+    }
+
+    /// The interval one resolution coarser which contains this one.  `None`
+    /// for times which cover the whole day: weeks don't line up with months,
+    /// so there's no tree above days.
+    pub fn parent(self) -> Option<Time> {
+        if self.resolution() <= Resolution::Day {
+            return None;
+        }
+        self.with_res(self.resolution().coarser()?)
+    }
 
-    // pub fn end(self) -> jiff::civil::Time {
-    //     todo!()
-    // }
+    /// The intervals one resolution finer which make up this one, in order.
+    /// Empty for millisecond times, and for times coarser than a day.
+    ///
+    /// ```
+    /// # use compactor::Time;
+    /// let hours: Vec<_> = Time::NIGHT.children().flat_map(|t| t.children()).collect();
+    /// assert_eq!(hours, (0..6).map(Time::from_hour).collect::<Vec<_>>());
+    /// ```
+    pub fn children(self) -> impl Iterator<Item = Time> {
+        let first = match self.resolution().finer() {
+            Some(res) if self.resolution() >= Resolution::Day => {
+                let bits = self.0.get() & !(1 << self.0.trailing_zeros());
+                Some(Time::from_bits(bits, res))
+            }
+            _ => None,
+        };
+        std::iter::successors(first, move |t| {
+            t.succ().filter(|t| t.parent() == Some(self))
+        })
+    }
 }
 
 impl Default for Time {
@@ -241,6 +492,13 @@ impl Time {
 impl Time {
     pub const WHOLE_DAY: Self = Time(NonZero::new(0b10000000_00000000_00000000_00000000).unwrap());
 
+    pub const WHOLE_WEEK: Self = Time(NonZero::new(0b00011100_00000000_00000000_00000000).unwrap());
+    pub const WHOLE_MONTH: Self =
+        Time(NonZero::new(0b00111100_00000000_00000000_00000000).unwrap());
+    pub const WHOLE_QUARTER: Self =
+        Time(NonZero::new(0b01011100_00000000_00000000_00000000).unwrap());
+    pub const WHOLE_YEAR: Self = Time(NonZero::new(0b01111100_00000000_00000000_00000000).unwrap());
+
     /// ```
     /// # use compactor::datetime::{AmPm, Time};
     /// assert_eq!(Time::AM, Time::new().with_am_pm(AmPm::AM));
@@ -501,6 +759,9 @@ impl Time {
 
 impl fmt::Debug for Time {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.resolution() < Resolution::Day {
+            return write!(f, "Time({})", self.resolution());
+        }
         f.write_str("Time(")?;
         let mut map = f.debug_map();
         for res in Resolution::range(self.resolution(), Resolution::Day).rev() {
@@ -517,6 +778,10 @@ impl fmt::Debug for Time {
 impl fmt::Display for Time {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self.resolution() {
+            Resolution::Year => f.write_str("whole year"),
+            Resolution::Quarter => f.write_str("whole quarter"),
+            Resolution::Month => f.write_str("whole month"),
+            Resolution::Week => f.write_str("whole week"),
             Resolution::Day => f.write_str("whole day"),
             Resolution::AmPm => write!(f, "{}", self.am_pm().unwrap()),
             Resolution::SixHour => write!(f, "{}", self.time_of_day().unwrap()),
@@ -646,6 +911,134 @@ mod tests {
         assert_eq!(t.time_of_day(), Some(SixHour::Evening));
     }
 
+    #[test]
+    fn test_succ() {
+        let mut t = Time::new().with_hour(0).with_minute(0);
+        let mut n = 1;
+        while let Some(next) = t.succ() {
+            assert_eq!(next.resolution(), Resolution::Minute);
+            assert_eq!(next.start_millis(), t.start_millis() + 60_000);
+            t = next;
+            n += 1;
+        }
+        assert_eq!(n, 24 * 60);
+        assert_eq!(Time::AM.succ(), Some(Time::PM));
+        assert_eq!(Time::PM.succ(), None);
+        assert_eq!(Time::WHOLE_DAY.succ(), None);
+        assert_eq!(Time::WHOLE_MONTH.succ(), None);
+    }
+
+    #[test]
+    fn test_intervals() {
+        let t = Time::from_hour(15).with_minute(7);
+        assert_eq!(t.start_millis(), (15 * 60 + 7) * 60_000);
+        assert_eq!(t.end_millis(), (15 * 60 + 8) * 60_000);
+        assert_eq!(Time::WHOLE_DAY.start_millis(), 0);
+        assert_eq!(Time::WHOLE_DAY.end_millis(), 24 * 60 * 60_000);
+        assert_eq!(Time::WHOLE_MONTH.end_millis(), 24 * 60 * 60_000);
+        assert_eq!(Time::AFTERNOON.start().as_secs(), 12 * 3600);
+        assert_eq!(Time::AFTERNOON.end().as_secs(), 18 * 3600);
+        let t = Time::from_hour(23)
+            .with_minute(59)
+            .with_second(59)
+            .with_millis(999);
+        assert_eq!(t.end_millis(), 24 * 60 * 60_000);
+    }
+
+    #[cfg(feature = "jiff")]
+    #[test]
+    fn test_intervals_jiff() {
+        let t = Time::from_hour(15).with_minute(7);
+        assert_eq!(t.start_jiff(), jiff::civil::time(15, 7, 0, 0));
+        assert_eq!(t.end_jiff(), jiff::civil::time(15, 8, 0, 0));
+        assert_eq!(Time::PM.end_jiff(), jiff::civil::time(0, 0, 0, 0));
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_intervals_chrono() {
+        let time = |h, m| chrono::NaiveTime::from_hms_opt(h, m, 0).unwrap();
+        let t = Time::from_hour(15).with_minute(7);
+        assert_eq!(t.start_chrono(), time(15, 7));
+        assert_eq!(t.end_chrono(), time(15, 8));
+        assert_eq!(Time::PM.end_chrono(), time(0, 0));
+    }
+
+    #[test]
+    fn test_tree() {
+        let t = Time::from_hour(15).with_minute(7).with_second(30);
+        assert!(Time::WHOLE_DAY.contains(t));
+        assert!(Time::WHOLE_WEEK.contains(t));
+        assert!(Time::WHOLE_WEEK.contains(Time::WHOLE_DAY));
+        assert!(!Time::WHOLE_DAY.contains(Time::WHOLE_WEEK));
+        assert!(Time::PM.contains(t) && !Time::AM.contains(t));
+        assert!(t.contains(t));
+        assert!(!t.contains(Time::PM));
+        assert!(t.overlaps(Time::PM) && Time::PM.overlaps(t));
+        assert!(!t.overlaps(Time::AM));
+        assert!(!Time::from_hour(3).overlaps(Time::from_hour(4)));
+        let tags = [
+            Time::WHOLE_DAY,
+            Time::WHOLE_WEEK,
+            Time::WHOLE_MONTH,
+            Time::WHOLE_QUARTER,
+            Time::WHOLE_YEAR,
+        ];
+        for x in tags {
+            assert!(tags.iter().all(|y| x.overlaps(*y) && y.overlaps(x)), "{x}");
+            assert!(x.overlaps(t) && !t.contains(x), "{x}");
+        }
+        assert!(!Time::WHOLE_WEEK.contains(Time::WHOLE_MONTH));
+        assert!(!Time::WHOLE_MONTH.contains(Time::WHOLE_WEEK));
+        assert!(Time::WHOLE_QUARTER.contains(Time::WHOLE_MONTH));
+        assert!(!Time::WHOLE_MONTH.contains(Time::WHOLE_QUARTER));
+
+        // Walk up to the root and back down again
+        let mut x = t;
+        while let Some(parent) = x.parent() {
+            assert_eq!(Some(parent.resolution()), x.resolution().coarser());
+            assert!(parent.contains(x));
+            assert!(parent.children().any(|c| c == x));
+            x = parent;
+        }
+        assert_eq!(x, Time::WHOLE_DAY);
+        assert_eq!(Time::WHOLE_WEEK.parent(), None);
+
+        for res in Resolution::variants().filter(|r| *r >= Resolution::Day) {
+            let mut t = Time::new()
+                .with_hour(13)
+                .with_minute(27)
+                .with_second(8)
+                .with_millis(777);
+            t.reduce_to(res);
+            let children: Vec<_> = t.children().collect();
+            let n = res.finer().map_or(0, |r| r.subdivision() as usize);
+            assert_eq!(children.len(), n, "{res}");
+            // The children span the parent, with no gaps
+            if let (Some(first), Some(last)) = (children.first(), children.last()) {
+                assert_eq!(first.start_millis(), t.start_millis());
+                assert_eq!(last.end_millis(), t.end_millis());
+            }
+            for w in children.windows(2) {
+                assert_eq!(w[0].end_millis(), w[1].start_millis());
+            }
+            assert!(children.iter().all(|c| c.parent() == Some(t)));
+        }
+        assert_eq!(Time::WHOLE_MONTH.children().count(), 0);
+    }
+
+    #[test]
+    fn test_first_last_milli() {
+        let t = Time::new().with_hour(15).with_minute(7);
+        assert_eq!(t.first_milli().to_string(), "15:07:00.000");
+        assert_eq!(t.last_milli().to_string(), "15:07:59.999");
+        assert_eq!(Time::PM.first_milli().to_string(), "12:00:00.000");
+        assert_eq!(Time::WHOLE_DAY.last_milli().to_string(), "23:59:59.999");
+        let t = t.with_second(3).with_millis(141);
+        assert_eq!(t.first_milli(), t);
+        assert_eq!(t.last_milli(), t);
+    }
+
     #[test]
     fn test_res_fmt() {
         let t = Time::new()
@@ -678,8 +1071,92 @@ mod tests {
                 Resolution::SixHour => "afternoon",
                 Resolution::AmPm => "PM",
                 Resolution::Day => "whole day",
+                Resolution::Week => "whole week",
+                Resolution::Month => "whole month",
+                Resolution::Quarter => "whole quarter",
+                Resolution::Year => "whole year",
             };
             assert_eq!(actual, expected);
         }
     }
+
+    #[test]
+    fn test_coarser_than_day() {
+        let tags = [
+            Time::WHOLE_WEEK,
+            Time::WHOLE_MONTH,
+            Time::WHOLE_QUARTER,
+            Time::WHOLE_YEAR,
+        ];
+        for tag in tags {
+            assert!(tag.resolution() < Resolution::Day);
+            assert_eq!(tag.with_res(tag.resolution()), Some(tag));
+            assert_eq!(tag.with_res(Resolution::Day), None);
+            assert_eq!(tag.partial_cmp(&Time::WHOLE_DAY), None);
+            assert_eq!(tag.partial_cmp(&Time::from_hour(7)), None);
+            assert_eq!(
+                tag.coarse_cmp(Time::from_hour(7)),
+                std::cmp::Ordering::Equal
+            );
+            assert_eq!(tag.first_milli(), Time::WHOLE_DAY.first_milli());
+            assert_eq!(tag.last_milli(), Time::WHOLE_DAY.last_milli());
+            assert_eq!(tag.hour(), 0);
+            assert_eq!(tag.am_pm(), None);
+        }
+        let mut t = Time::from_hour(7);
+        t.reduce_to(Resolution::Month);
+        assert_eq!(t, Time::WHOLE_MONTH);
+        t.reduce_to(Resolution::Week);
+        assert_eq!(t, Time::WHOLE_MONTH);
+        t.reduce_to(Resolution::Year);
+        assert_eq!(t, Time::WHOLE_YEAR);
+        assert_eq!(format!("{t:?}"), "Time(year)");
+    }
+
+    #[test]
+    fn test_tags() {
+        let tags = [
+            Time::WHOLE_WEEK,
+            Time::WHOLE_MONTH,
+            Time::WHOLE_QUARTER,
+            Time::WHOLE_YEAR,
+        ];
+        for tag in tags {
+            // They look like hours...
+            assert_eq!(
+                tag.0.trailing_zeros(),
+                Resolution::Hour.trailing_zeros() as u32
+            );
+            assert!(tag.resolution() < Resolution::Day);
+            assert_eq!(tag.succ(), None);
+            assert_eq!(tag.children().count(), 0);
+        }
+        // ...but no real hour has the same bits
+        for h in 0..24 {
+            let t = Time::from_hour(h);
+            assert!(!tags.contains(&t), "{t}");
+            assert_eq!(t.resolution(), Resolution::Hour);
+        }
+        let hours: Vec<_> = std::iter::successors(Some(Time::from_hour(0)), |t| t.succ()).collect();
+        assert_eq!(hours.len(), 24);
+    }
+
+    #[test]
+    fn test_try_from_bits() {
+        let t = Time::new()
+            .with_hour(23)
+            .with_minute(59)
+            .with_second(7)
+            .with_millis(42);
+        for res in Resolution::variants() {
+            let t = t.with_res(res).unwrap();
+            assert_eq!(Time::try_from_bits(t.to_bits()), Some(t));
+        }
+        assert_eq!(Time::try_from_bits(0), None);
+        // An hour of 24
+        let bits = Time::from_hour(23).to_bits() + (1 << 27);
+        assert_eq!(Time::try_from_bits(bits), None);
+        // A resolution which isn't in the tree
+        assert_eq!(Time::try_from_bits(1 << 1), None);
+    }
 }
@@ -0,0 +1,172 @@
+use super::{Date, Time};
+use core::fmt;
+use std::time::{Duration, SystemTime};
+
+const MILLIS_PER_DAY: i64 = 24 * 60 * 60 * 1000;
+
+/// A UTC instant, as a number of milliseconds since the Unix epoch
+///
+/// This is for getting data in and out of a compactor without going through a
+/// date/time library.  It converts to a `(Date, Time)` pair at millisecond
+/// resolution:
+///
+/// ```
+/// # use compactor::{Date, Time, UnixTime};
+/// let (date, time) = UnixTime::from_secs(1_700_000_000).try_into().unwrap();
+/// assert_eq!(date.to_string(), "2023-11-14");
+/// assert_eq!(time.to_string(), "22:13:20.000");
+/// ```
+///
+/// Converting back gives the start of the interval covered by the `Time`:
+///
+/// ```
+/// # use compactor::{Date, Time, UnixTime};
+/// let date = Date { year: 2023, month: 11, day: 14 };
+/// let time = Time::from_hour(22);
+/// assert_eq!(UnixTime::from((date, time)), UnixTime::from_secs(1_699_999_200));
+/// ```
+///
+/// Conversions to `Date` fail with [`OutOfRange`] if the instant is out of
+/// `Date`'s range.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UnixTime(i64);
+
+impl UnixTime {
+    /// Saturates if `secs` is too big to fit in milliseconds, which is
+    /// hundreds of millions of years out of `Date`'s range anyway
+    pub const fn from_secs(secs: i64) -> Self {
+        UnixTime(secs.saturating_mul(1000))
+    }
+
+    pub const fn from_millis(millis: i64) -> Self {
+        UnixTime(millis)
+    }
+
+    /// Rounds towards the past
+    pub const fn as_secs(self) -> i64 {
+        self.0.div_euclid(1000)
+    }
+
+    pub const fn as_millis(self) -> i64 {
+        self.0
+    }
+}
+
+/// The instant is too far from the epoch to be represented as a [`Date`]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct OutOfRange;
+
+impl fmt::Display for OutOfRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Instant out of range")
+    }
+}
+
+impl std::error::Error for OutOfRange {}
+
+impl TryFrom<UnixTime> for (Date, Time) {
+    type Error = OutOfRange;
+
+    fn try_from(t: UnixTime) -> Result<Self, OutOfRange> {
+        let days = t.0.div_euclid(MILLIS_PER_DAY);
+        let millis = t.0.rem_euclid(MILLIS_PER_DAY) as u32;
+        let date = jiff::Span::new()
+            .try_days(days)
+            .and_then(|span| jiff::civil::date(1970, 1, 1).checked_add(span))
+            .map_err(|_| OutOfRange)?;
+        let date = Date::from_civil(date);
+        let secs = millis / 1000;
+        let time = Time::new()
+            .with_hour((secs / 60 / 60) as u8)
+            .with_minute((secs / 60 % 60) as u8)
+            .with_second((secs % 60) as u8)
+            .with_millis((millis % 1000) as u16);
+        Ok((date, time))
+    }
+}
+
+impl From<(Date, Time)> for UnixTime {
+    fn from((date, time): (Date, Time)) -> Self {
+        let days = (date.to_civil() - jiff::civil::date(1970, 1, 1)).get_days() as i64;
+        UnixTime(days * MILLIS_PER_DAY + time.start_millis() as i64)
+    }
+}
+
+impl From<SystemTime> for UnixTime {
+    fn from(t: SystemTime) -> Self {
+        match t.duration_since(SystemTime::UNIX_EPOCH) {
+            Ok(d) => UnixTime(i64::try_from(d.as_millis()).unwrap_or(i64::MAX)),
+            // Round towards the past, like the positive case
+            Err(e) => UnixTime(
+                i64::try_from(e.duration().as_micros().div_ceil(1000)).map_or(i64::MIN, |x| -x),
+            ),
+        }
+    }
+}
+
+impl From<UnixTime> for SystemTime {
+    fn from(t: UnixTime) -> Self {
+        let d = Duration::from_millis(t.0.unsigned_abs());
+        if t.0 >= 0 {
+            SystemTime::UNIX_EPOCH + d
+        } else {
+            SystemTime::UNIX_EPOCH - d
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        for millis in [
+            0,
+            1,
+            999,
+            MILLIS_PER_DAY - 1,
+            MILLIS_PER_DAY,
+            1_700_000_000_123,
+            -1,
+            -MILLIS_PER_DAY,
+            -1_700_000_000_123,
+        ] {
+            let t = UnixTime::from_millis(millis);
+            let (date, time) = t.try_into().unwrap();
+            assert_eq!(UnixTime::from((date, time)), t, "{date} {time}");
+            assert_eq!(UnixTime::from(SystemTime::from(t)), t);
+        }
+    }
+
+    #[test]
+    fn test_before_epoch() {
+        let (date, time) = UnixTime::from_millis(-1).try_into().unwrap();
+        assert_eq!(date.to_string(), "1969-12-31");
+        assert_eq!(time.to_string(), "23:59:59.999");
+        assert_eq!(UnixTime::from_millis(-1).as_secs(), -1);
+        let t = SystemTime::UNIX_EPOCH - Duration::from_micros(1500);
+        assert_eq!(UnixTime::from(t), UnixTime::from_millis(-2));
+    }
+
+    #[test]
+    fn test_saturate() {
+        assert_eq!(UnixTime::from_secs(i64::MAX).as_millis(), i64::MAX);
+        assert_eq!(UnixTime::from_secs(i64::MIN).as_millis(), i64::MIN);
+        assert_eq!(UnixTime::from_secs(-5).as_millis(), -5000);
+        if let Some(t) = SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(1 << 60)) {
+            assert_eq!(UnixTime::from(t).as_millis(), i64::MAX);
+        }
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        for millis in [i64::MAX, i64::MIN, 10_000 * 366 * MILLIS_PER_DAY] {
+            let t = UnixTime::from_millis(millis);
+            assert_eq!(<(Date, Time)>::try_from(t), Err(OutOfRange));
+        }
+        let (date, _) = UnixTime::from_secs(253_402_300_799).try_into().unwrap();
+        assert_eq!(date.to_string(), "9999-12-31");
+    }
+}
@@ -0,0 +1,258 @@
+//! Filling in the gaps in [`Compactor::iter_grid`](crate::Compactor::iter_grid)
+//!
+//! ```
+//! # use compactor::{Compactor, Date, Resolution, Time, aggregate::Max, grid::FillGaps};
+//! let mut compactor = Compactor::new()
+//!     .keep_for_days(1, Resolution::Hour)
+//!     .build()
+//!     .unwrap();
+//! let date = Date { year: 2024, month: 3, day: 1 };
+//! compactor.push(date, Time::from_hour(1), Max(10)).unwrap();
+//! compactor.push(date, Time::from_hour(4), Max(40)).unwrap();
+//! let xs: Vec<_> = compactor
+//!     .iter_grid(Resolution::Hour, date, date)
+//!     .map(|(d, t, x)| (d, t, x.map(|x| x.0)))
+//!     .fill_linear()
+//!     .take(6)
+//!     .map(|(_, _, x)| x)
+//!     .collect();
+//! assert_eq!(xs, [None, Some(10.0), Some(20.0), Some(30.0), Some(40.0), None]);
+//! ```
+
+use crate::{
+    Date, Resolution, Time, UnixTime,
+    aggregate::Numeric,
+    data::{first_milli, last_milli},
+};
+use std::collections::VecDeque;
+
+/// Every bucket at resolution `res` from `start` to `end` (inclusive), in
+/// order
+pub(crate) fn cells(res: Resolution, start: Date, end: Date) -> impl Iterator<Item = (Date, Time)> {
+    let mut first = Time::WHOLE_DAY.first_milli();
+    first.reduce_to(res);
+    let mut date = start;
+    date.reduce_to(res);
+    let mut next = Some((date, first));
+    std::iter::from_fn(move || {
+        let (date, time) = next.filter(|(d, _)| *d <= end)?;
+        next = match time.succ() {
+            Some(time) => Some((date, time)),
+            None => date
+                .period_end(res)
+                .to_civil()
+                .tomorrow()
+                .ok()
+                .map(|d| (Date::from_civil(d), first)),
+        };
+        Some((date, time))
+    })
+}
+
+/// Puts `xs` into the grid of `cells`, with `None` for the empty ones.  An
+/// entry which is coarser than the grid takes the place of all the cells it
+/// covers.
+pub(crate) fn fill_grid<T>(
+    cells: impl Iterator<Item = (Date, Time)>,
+    xs: impl Iterator<Item = (Date, Time, T)>,
+) -> impl Iterator<Item = (Date, Time, Option<T>)> {
+    let mut cells = cells.peekable();
+    let mut xs = xs.peekable();
+    std::iter::from_fn(move || {
+        let Some((d, t, _)) = xs.peek() else {
+            return cells.next().map(|(d, t)| (d, t, None));
+        };
+        let (start, end) = (first_milli(*d, *t), last_milli(*d, *t));
+        if let Some((cd, ct)) = cells.next_if(|(cd, ct)| last_milli(*cd, *ct) < start) {
+            return Some((cd, ct, None));
+        }
+        while cells
+            .next_if(|(cd, ct)| first_milli(*cd, *ct) <= end)
+            .is_some()
+        {}
+        xs.next().map(|(d, t, x)| (d, t, Some(x)))
+    })
+}
+
+/// Ways to fill in the empty buckets of a grid
+///
+/// This is implemented for any iterator like the one returned by
+/// [`Compactor::iter_grid`](crate::Compactor::iter_grid).  You may want to
+/// `map` the values to something simpler first.
+pub trait FillGaps<T>: Iterator<Item = (Date, Time, Option<T>)> + Sized {
+    /// Repeat the last value.  Buckets before the first value stay empty.
+    fn fill_previous(self) -> impl Iterator<Item = (Date, Time, Option<T>)>
+    where
+        T: Clone,
+    {
+        let mut prev = None;
+        self.map(move |(d, t, x)| match x {
+            Some(x) => {
+                prev = Some(x.clone());
+                (d, t, Some(x))
+            }
+            None => (d, t, prev.clone()),
+        })
+    }
+
+    /// Use `T::default()`, which is zero for [`Sum`](crate::aggregate::Sum),
+    /// [`Count`](crate::aggregate::Count), etc.
+    fn fill_default(self) -> impl Iterator<Item = (Date, Time, T)>
+    where
+        T: Default,
+    {
+        self.map(|(d, t, x)| (d, t, x.unwrap_or_default()))
+    }
+
+    /// Draw a straight line between the values on either side.  Buckets
+    /// before the first value or after the last one stay empty.
+    ///
+    /// The line goes through the start of each bucket, so this gives sensible
+    /// results even when some buckets are coarser than others.
+    fn fill_linear(self) -> impl Iterator<Item = (Date, Time, Option<f64>)>
+    where
+        T: Numeric,
+    {
+        let mut xs = self.fuse();
+        let mut prev: Option<(i64, f64)> = None;
+        // Empty buckets which we don't know the value of yet
+        let mut gap = VecDeque::new();
+        let mut out = VecDeque::new();
+        std::iter::from_fn(move || {
+            loop {
+                if let Some(x) = out.pop_front() {
+                    return Some(x);
+                }
+                let Some((d, t, x)) = xs.next() else {
+                    // No more values, so the gap can't be filled
+                    return gap.pop_front().map(|(d, t)| (d, t, None));
+                };
+                let Some(x) = x else {
+                    gap.push_back((d, t));
+                    continue;
+                };
+                let (t1, x1) = (UnixTime::from((d, t)).as_millis(), x.to_f64());
+                for (gd, gt) in gap.drain(..) {
+                    let y = prev.map(|(t0, x0)| {
+                        let t = UnixTime::from((gd, gt)).as_millis();
+                        x0 + (x1 - x0) * (t - t0) as f64 / (t1 - t0) as f64
+                    });
+                    out.push_back((gd, gt, y));
+                }
+                out.push_back((d, t, Some(x1)));
+                prev = Some((t1, x1));
+            }
+        })
+    }
+}
+
+impl<T, I: Iterator<Item = (Date, Time, Option<T>)>> FillGaps<T> for I {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i16, month: i8, day: i8) -> Date {
+        Date { year, month, day }
+    }
+
+    #[test]
+    fn test_cells() {
+        let d1 = date(2024, 2, 28);
+        let d2 = date(2024, 3, 1);
+        assert_eq!(cells(Resolution::Minute, d1, d2).count(), 3 * 24 * 60);
+        let xs: Vec<_> = cells(Resolution::AmPm, d1, d2).collect();
+        assert_eq!(xs.len(), 6);
+        assert_eq!(xs[0], (d1, Time::AM));
+        assert_eq!(xs[3], (date(2024, 2, 29), Time::PM));
+        let xs: Vec<_> = cells(Resolution::Day, d1, d2).map(|x| x.0.day).collect();
+        assert_eq!(xs, [28, 29, 1]);
+        let xs: Vec<_> = cells(Resolution::Week, d1, date(2024, 3, 11)).collect();
+        assert_eq!(
+            xs,
+            [
+                (date(2024, 2, 26), Time::WHOLE_WEEK),
+                (date(2024, 3, 4), Time::WHOLE_WEEK),
+                (date(2024, 3, 11), Time::WHOLE_WEEK)
+            ]
+        );
+        let xs: Vec<_> = cells(Resolution::Quarter, d1, date(2025, 1, 1)).collect();
+        assert_eq!(xs.len(), 5);
+        assert_eq!(xs[4], (date(2025, 1, 1), Time::WHOLE_QUARTER));
+        assert_eq!(cells(Resolution::Hour, d2, d1).count(), 0);
+    }
+
+    fn values<X>(xs: impl IntoIterator<Item = (Date, Time, X)>) -> Vec<X> {
+        xs.into_iter().map(|x| x.2).collect()
+    }
+
+    #[test]
+    fn test_fill() {
+        let d = date(2024, 3, 1);
+        let grid = || {
+            let xs = [(1, 10), (2, 20), (5, 50)];
+            fill_grid(
+                cells(Resolution::Hour, d, d),
+                xs.into_iter().map(|(h, x)| (d, Time::from_hour(h), x)),
+            )
+            .take(8)
+        };
+        let xs: Vec<_> = grid().collect();
+        assert_eq!(xs.len(), 8);
+        assert!(
+            xs.iter()
+                .enumerate()
+                .all(|(h, x)| x.1 == Time::from_hour(h as u8))
+        );
+        assert_eq!(
+            values(xs),
+            [None, Some(10), Some(20), None, None, Some(50), None, None]
+        );
+        assert_eq!(
+            values(grid().fill_previous()),
+            [
+                None,
+                Some(10),
+                Some(20),
+                Some(20),
+                Some(20),
+                Some(50),
+                Some(50),
+                Some(50)
+            ]
+        );
+        assert_eq!(values(grid().fill_default()), [0, 10, 20, 0, 0, 50, 0, 0]);
+        assert_eq!(
+            values(grid().fill_linear()),
+            [
+                None,
+                Some(10.0),
+                Some(20.0),
+                Some(30.0),
+                Some(40.0),
+                Some(50.0),
+                None,
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn test_coarse_entries() {
+        let d = date(2024, 3, 1);
+        let three_hours = |h| Time::from_hour(h).with_res(Resolution::ThreeHour).unwrap();
+        let xs = [(d, Time::AM, 1), (d, three_hours(15), 2)];
+        let grid: Vec<_> = fill_grid(cells(Resolution::ThreeHour, d, d), xs.into_iter()).collect();
+        // The AM bucket takes the place of the first four cells
+        assert_eq!(
+            grid,
+            [
+                (d, Time::AM, Some(1)),
+                (d, three_hours(12), None),
+                (d, three_hours(15), Some(2)),
+                (d, three_hours(18), None),
+                (d, three_hours(21), None),
+            ]
+        );
+    }
+}
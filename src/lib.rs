@@ -7,7 +7,7 @@ You specify a compaction policy, like this:
 ```rust
 # use compactor::{Compactor, policy::PolicyError, Resolution};
 # fn foo() -> Result<(), PolicyError> {
-# struct MyData(usize);
+# struct MyData { total: f64, count: u64 }
 let mut compactor = Compactor::<MyData>::new()
     .keep_for_days(7, Resolution::FiveMinute)
     .keep_for_days(30, Resolution::Hour)
@@ -21,17 +21,18 @@ You specify how to compact your data, like this:
 
 ```rust
 # use compactor::Aggregate;
-# #[derive(Copy, Clone)]
-# struct MyData(usize);
-# impl std::ops::Add for MyData { type Output = MyData; fn add(self, other: MyData) -> MyData { MyData(self.0 + other.0) } }
-# impl std::ops::Div<usize> for MyData { type Output = MyData; fn div(self, other: usize) -> MyData { MyData(self.0 / other) } }
+# struct MyData { total: f64, count: u64 }
 impl Aggregate for MyData {
     fn merge(&mut self, other: MyData) {
-        *self = (*self + other) / 2;
+        self.total += other.total;
+        self.count += other.count;
     }
 }
 ```
 
+(Or use one of the types in [`aggregate`], like [`Mean`](aggregate::Mean),
+which does the same thing.)
+
 ...and then you can start pushing data into the compactor.
 
 In this example, data will initially be stored at "five-minute" resolution,
@@ -46,11 +47,22 @@ older than 100 days will be deleted.
 */
 
 pub mod aggregate;
+pub mod codec;
 mod compactor;
 mod data;
 pub mod datetime;
+pub mod grid;
+mod map;
 pub mod policy;
+pub mod store;
+#[cfg(feature = "jiff")]
+mod zoned;
 
-pub use crate::aggregate::Aggregate;
-pub use crate::compactor::{Compactor, CompactorBuilder};
-pub use crate::datetime::{Date, Resolution, Time};
+pub use crate::aggregate::{Aggregate, CommutativeAggregate, InvertibleAggregate};
+pub use crate::compactor::{Compactor, CompactorBuilder, PushError, RetractError};
+pub use crate::datetime::{Date, OutOfRange, Resolution, Time, UnixTime};
+pub use crate::map::CompactorMap;
+#[cfg(feature = "jiff")]
+pub use crate::zoned::ZonedCompactor;
+#[cfg(feature = "derive")]
+pub use compactor_derive::Aggregate;
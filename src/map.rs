@@ -0,0 +1,350 @@
+use crate::{
+    Aggregate, Compactor, Date, InvertibleAggregate, Time, UnixTime,
+    aggregate::FromSample,
+    compactor::{PushError, RetractError},
+    data::CompactedData,
+};
+use std::{borrow::Borrow, collections::HashMap, hash::Hash};
+
+/// A collection of [`Compactor`]s, one per key, which share a policy
+///
+/// The series all move through time together: when a value is pushed with a
+/// new date, every series is compacted, not just the one the value was pushed
+/// to.  This means that a series which stops receiving data still gets
+/// compacted, and eventually emptied.
+///
+/// ```
+/// # use compactor::{Compactor, Date, Resolution, Time};
+/// let mut map = Compactor::new()
+///     .keep_for_days(2, Resolution::Hour)
+///     .build_map()
+///     .unwrap();
+/// let date = Date { year: 2024, month: 3, day: 1 };
+/// map.push("cpu", date, Time::from_hour(9), vec![0.5]).unwrap();
+/// map.push("mem", date, Time::from_hour(9), vec![0.8]).unwrap();
+/// assert_eq!(map.len(), 2);
+///
+/// // Nothing is pushed to "mem" after this, so its data expires
+/// let date = Date { year: 2024, month: 3, day: 3 };
+/// map.push("cpu", date, Time::from_hour(9), vec![0.6]).unwrap();
+/// assert!(map.get("mem").unwrap().is_empty());
+/// map.remove_empty();
+/// assert_eq!(map.keys().collect::<Vec<_>>(), vec![&"cpu"]);
+/// ```
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(
+        bound(
+            serialize = "K: serde::Serialize, T: serde::Serialize",
+            deserialize = "K: Eq + Hash + serde::Deserialize<'de>, T: serde::Deserialize<'de>"
+        ),
+        from = "SerdeMap<K, T>"
+    )
+)]
+pub struct CompactorMap<K, T> {
+    /// An empty compactor, which new series are cloned from
+    template: Compactor<T>,
+    /// Only the data is serialized: the policy is the template's
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_series"))]
+    series: HashMap<K, Compactor<T>>,
+    /// The newest date seen so far
+    date: Option<Date>,
+}
+
+#[cfg(feature = "serde")]
+fn serialize_series<S, K, T>(series: &HashMap<K, Compactor<T>>, s: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+    K: serde::Serialize,
+    T: serde::Serialize,
+{
+    s.collect_map(series.iter().map(|(k, series)| (k, &series.data)))
+}
+
+/// What a `CompactorMap` looks like when it's serialized
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+#[serde(bound(deserialize = "K: Eq + Hash + serde::Deserialize<'de>, T: serde::Deserialize<'de>"))]
+struct SerdeMap<K, T> {
+    template: Compactor<T>,
+    series: HashMap<K, CompactedData<T>>,
+    date: Option<Date>,
+}
+
+#[cfg(feature = "serde")]
+impl<K: Eq + Hash, T> From<SerdeMap<K, T>> for CompactorMap<K, T> {
+    fn from(map: SerdeMap<K, T>) -> Self {
+        let series = map
+            .series
+            .into_iter()
+            .map(|(k, data)| {
+                let series = Compactor {
+                    policy: map.template.policy.clone(),
+                    data,
+                    late_horizon: map.template.late_horizon,
+                    date: map.date,
+                };
+                (k, series)
+            })
+            .collect();
+        CompactorMap {
+            template: map.template,
+            series,
+            date: map.date,
+        }
+    }
+}
+
+impl<K, T> From<Compactor<T>> for CompactorMap<K, T> {
+    /// Takes the settings from the given compactor.  Any data it contains is
+    /// ignored.
+    fn from(mut template: Compactor<T>) -> Self {
+        template.data = CompactedData::default();
+        CompactorMap {
+            template,
+            series: HashMap::new(),
+            date: None,
+        }
+    }
+}
+
+impl<K: Eq + Hash, T: Aggregate> CompactorMap<K, T> {
+    /// Push a value to the series for `key`, creating it if it doesn't exist.
+    /// See [`Compactor::push`].
+    ///
+    /// Each series must be pushed in order, but they don't need to be in
+    /// order with each other.
+    pub fn push(
+        &mut self,
+        key: K,
+        date: impl Into<Date>,
+        time: impl Into<Time>,
+        x: T,
+    ) -> Result<(), PushError> {
+        let date = date.into();
+        let series = self.series.entry(key).or_insert_with(|| Compactor {
+            policy: self.template.policy.clone(),
+            data: CompactedData::default(),
+            late_horizon: self.template.late_horizon,
+            date: self.date,
+        });
+        // This compacts the series if it's a new day...
+        series.push(date, time, x)?;
+        // ...and this compacts the others
+        self.update_date(date);
+        Ok(())
+    }
+
+    /// Push a sample to the series for `key`.  See [`Compactor::push_sample`].
+    pub fn push_sample<V>(
+        &mut self,
+        key: K,
+        date: impl Into<Date>,
+        time: impl Into<Time>,
+        x: V,
+    ) -> Result<(), PushError>
+    where
+        T: FromSample<V>,
+    {
+        let (date, time) = (date.into(), time.into());
+        self.push(
+            key,
+            date,
+            time,
+            T::from_sample(UnixTime::from((date, time)), x),
+        )
+    }
+
+    /// Take a value back out of the series for `key`.  See
+    /// [`Compactor::retract`].
+    pub fn retract<Q>(
+        &mut self,
+        key: &Q,
+        date: impl Into<Date>,
+        time: impl Into<Time>,
+        x: T,
+    ) -> Result<(), RetractError>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        T: InvertibleAggregate,
+    {
+        let series = self.series.get_mut(key).ok_or(RetractError::NotFound)?;
+        series.retract(date, time, x)
+    }
+
+    /// Update the current date without pushing any new data.  If it's newer
+    /// than the current date, every series is compacted.
+    pub fn update_date(&mut self, date: impl Into<Date>) {
+        let date = date.into();
+        if self.date.is_none_or(|d| date > d) {
+            self.date = Some(date);
+            for series in self.series.values_mut() {
+                series.update_date(date);
+            }
+        }
+    }
+}
+
+impl<K: Eq + Hash, T> CompactorMap<K, T> {
+    pub fn get<Q>(&self, key: &Q) -> Option<&Compactor<T>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.series.get(key)
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<Compactor<T>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.series.remove(key)
+    }
+
+    /// Remove the series which no longer contain any data
+    pub fn remove_empty(&mut self) {
+        self.series.retain(|_, series| !series.is_empty());
+    }
+}
+
+impl<K, T> CompactorMap<K, T> {
+    /// The settings which every series uses
+    pub fn template(&self) -> &Compactor<T> {
+        &self.template
+    }
+
+    /// The number of series
+    pub fn len(&self) -> usize {
+        self.series.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.series.is_empty()
+    }
+
+    /// In arbitrary order
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.series.keys()
+    }
+
+    /// The series, in arbitrary order
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &Compactor<T>)> {
+        self.series.iter()
+    }
+
+    /// The entries of every series.  The series come in arbitrary order, and
+    /// the entries within each series go from old -> new.
+    pub fn entries(&self) -> impl Iterator<Item = (&K, Date, Time, &T)> {
+        self.series
+            .iter()
+            .flat_map(|(k, series)| series.iter().map(move |(d, t, x)| (k, d, t, x)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Resolution;
+
+    fn date(year: i16, month: i8, day: i8) -> Date {
+        Date { year, month, day }
+    }
+
+    fn map() -> CompactorMap<String, Vec<u32>> {
+        Compactor::new()
+            .keep_for_days(2, Resolution::Hour)
+            .keep_for_days(4, Resolution::Day)
+            .build_map()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_shared_policy() -> Result<(), PushError> {
+        let mut map = map();
+        map.push("a".into(), date(2024, 3, 1), Time::from_hour(9), vec![1])?;
+        map.push("b".into(), date(2024, 3, 1), Time::from_hour(9), vec![2])?;
+        let a = map.get("a").unwrap().policy();
+        let b = map.get("b").unwrap().policy();
+        assert!(std::ptr::eq(a, b));
+        Ok(())
+    }
+
+    #[test]
+    fn test_compaction() -> Result<(), PushError> {
+        let mut map = map();
+        for d in 1..=3 {
+            map.push("a".into(), date(2024, 3, d), Time::from_hour(9), vec![1])?;
+            map.push("a".into(), date(2024, 3, d), Time::from_hour(10), vec![2])?;
+        }
+        map.push("b".into(), date(2024, 3, 2), Time::from_hour(9), vec![3])?;
+        map.push("b".into(), date(2024, 3, 2), Time::from_hour(10), vec![4])?;
+        // Pushing to "a" compacts "b" too
+        map.push("a".into(), date(2024, 3, 4), Time::from_hour(9), vec![1])?;
+        assert_eq!(
+            map.get("b").unwrap().iter().collect::<Vec<_>>(),
+            vec![(date(2024, 3, 2), Time::WHOLE_DAY, &vec![3, 4])]
+        );
+        assert_eq!(map.entries().count(), 6);
+        assert_eq!(map.entries().filter(|(k, ..)| *k == "b").count(), 1);
+
+        // Series don't need to be in order with each other
+        map.push("b".into(), date(2024, 3, 3), Time::from_hour(9), vec![5])?;
+        assert_eq!(
+            map.push("b".into(), date(2024, 3, 2), Time::from_hour(9), vec![6]),
+            Err(PushError::NonMonotonic)
+        );
+
+        map.update_date(date(2024, 3, 7));
+        assert!(map.get("b").unwrap().is_empty());
+        assert!(!map.get("a").unwrap().is_empty());
+        map.remove_empty();
+        assert_eq!(map.keys().collect::<Vec<_>>(), vec!["a"]);
+        assert!(map.remove("a").is_some());
+        assert!(map.is_empty());
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() -> Result<(), PushError> {
+        use std::sync::Arc;
+        let mut map = map();
+        map.push("a".into(), date(2024, 3, 1), Time::from_hour(9), vec![1])?;
+        map.push("b".into(), date(2024, 3, 2), Time::from_hour(9), vec![2])?;
+        let json = serde_json::to_string(&map).unwrap();
+        let map2: CompactorMap<String, Vec<u32>> = serde_json::from_str(&json).unwrap();
+        assert_eq!(map2.date, map.date);
+        assert_eq!(map2.template.policy, map.template.policy);
+        for (k, series) in map.iter() {
+            let series2 = map2.get(k).unwrap();
+            assert_eq!(series2.data, series.data);
+            assert!(Arc::ptr_eq(&series2.policy, &map2.template.policy));
+        }
+        assert_eq!(map2.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_retract() -> Result<(), PushError> {
+        use crate::aggregate::Count;
+        let mut map = Compactor::new()
+            .keep_for_days(2, Resolution::Hour)
+            .build_map::<&str>()
+            .unwrap();
+        map.push("a", date(2024, 3, 1), Time::from_hour(9), Count(3))?;
+        map.push("b", date(2024, 3, 1), Time::from_hour(9), Count(4))?;
+        map.retract("a", date(2024, 3, 1), Time::from_hour(9), Count(1))
+            .unwrap();
+        let counts: Vec<_> = map.entries().map(|(k, _, _, x)| (*k, x.0)).collect();
+        assert!(counts.contains(&("a", 2)) && counts.contains(&("b", 4)));
+        assert_eq!(
+            map.retract("c", date(2024, 3, 1), Time::from_hour(9), Count(1)),
+            Err(RetractError::NotFound)
+        );
+        Ok(())
+    }
+}
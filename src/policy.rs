@@ -1,5 +1,6 @@
 use crate::Resolution;
 use core::fmt;
+use std::str::FromStr;
 
 type Days = u16;
 
@@ -11,6 +12,8 @@ pub struct Policy {
     pub(crate) compaction_rules: Box<[(Days, Resolution)]>,
     pub(crate) max_res: Resolution,
     pub(crate) max_retention: Days,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) len_budget: Option<usize>,
 }
 
 impl fmt::Display for Policy {
@@ -21,17 +24,176 @@ impl fmt::Display for Policy {
                 writeln!(f, "After {d} days: reduce to {res}-resolution")?;
             }
             write!(f, "After {} days: delete", self.max_retention)?;
+            if let Some(n) = self.len_budget {
+                write!(f, "\nKeep at most {n} entries")?;
+            }
         } else {
             write!(f, "{}", self.max_res)?;
             for (d, res) in self.compaction_rules.iter().rev() {
                 write!(f, " →  ({d}d) {res}")?;
             }
             write!(f, " →  ({}d) delete", self.max_retention)?;
+            if let Some(n) = self.len_budget {
+                write!(f, " (max {n})")?;
+            }
         }
         Ok(())
     }
 }
 
+/// Accepts either of the `Display` forms, or a comma-separated list of
+/// `resolution:days` pairs, as passed to [`PolicyBuilder::keep_for_days`].  In
+/// the compact `Display` form, "->" can be used instead of "→".
+///
+/// ```
+/// # use compactor::{policy::Policy, Resolution};
+/// let policy = Policy::new()
+///     .keep_for_days(1, Resolution::FiveSecond)
+///     .keep_for_days(2, Resolution::FifteenSecond)
+///     .keep_for_days(5, Resolution::Minute)
+///     .build()
+///     .unwrap();
+/// assert_eq!("5s:1d,15s:2d,1m:5d".parse(), Ok(policy.clone()));
+/// assert_eq!("5s -> (1d) 15s -> (2d) minute -> (5d) delete".parse(), Ok(policy.clone()));
+/// assert_eq!(format!("{policy:#}").parse(), Ok(policy));
+/// ```
+///
+/// A length budget can be given as "max:N" in the list form.
+impl FromStr for Policy {
+    type Err = PolicyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let builder = if s.starts_with("Initial:") {
+            parse_verbose(s)?
+        } else if s.contains(':') {
+            parse_list(s)?
+        } else {
+            parse_compact(s)?
+        };
+        builder.build()
+    }
+}
+
+fn bad_syntax(s: &str) -> PolicyError {
+    PolicyError::BadSyntax(s.trim().to_string())
+}
+
+fn parse_days(s: &str) -> Result<Days, PolicyError> {
+    let s = s.trim();
+    let s = s.strip_suffix('d').unwrap_or(s);
+    s.trim().parse().map_err(|_| bad_syntax(s))
+}
+
+fn parse_len(s: &str) -> Result<usize, PolicyError> {
+    s.trim().parse().map_err(|_| bad_syntax(s))
+}
+
+/// "5s:1d,15s:2d,1m:5d"
+fn parse_list(s: &str) -> Result<PolicyBuilder, PolicyError> {
+    let mut builder = Policy::new();
+    for item in s.split(',') {
+        let (lhs, rhs) = item.split_once(':').ok_or_else(|| bad_syntax(item))?;
+        builder = if lhs.trim().eq_ignore_ascii_case("max") {
+            builder.len_budget(parse_len(rhs)?)
+        } else {
+            builder.keep_for_days(parse_days(rhs)?, lhs.parse()?)
+        };
+    }
+    Ok(builder)
+}
+
+/// "5s →  (1d) 15s →  (2d) minute →  (5d) delete (max 100)"
+fn parse_compact(s: &str) -> Result<PolicyBuilder, PolicyError> {
+    let s = s.replace("->", "→");
+    let mut steps = s.split('→');
+    let mut res = steps.next().unwrap_or_default().parse()?;
+    let mut builder = Policy::new();
+    let mut deleted = false;
+    for step in steps {
+        if deleted {
+            return Err(bad_syntax(step));
+        }
+        let (days, rest) = step
+            .trim()
+            .strip_prefix('(')
+            .and_then(|x| x.split_once(')'))
+            .ok_or_else(|| bad_syntax(step))?;
+        builder = builder.keep_for_days(parse_days(days)?, res);
+        // Keywords are case-insensitive, like resolutions
+        let lower = rest.trim().to_ascii_lowercase();
+        match lower.strip_prefix("delete") {
+            Some("") => deleted = true,
+            Some(max) => {
+                let max = max
+                    .trim()
+                    .strip_prefix("(max ")
+                    .and_then(|x| x.strip_suffix(')'))
+                    .ok_or_else(|| bad_syntax(rest))?;
+                builder = builder.len_budget(parse_len(max)?);
+                deleted = true;
+            }
+            None => res = rest.parse()?,
+        }
+    }
+    if !deleted {
+        return Err(bad_syntax(&s));
+    }
+    Ok(builder)
+}
+
+/// The alternate `Display` form, one rule per line
+fn parse_verbose(s: &str) -> Result<PolicyBuilder, PolicyError> {
+    fn parse_res(s: &str) -> Result<Resolution, PolicyError> {
+        s.trim()
+            .strip_suffix("-resolution")
+            .ok_or_else(|| bad_syntax(s))?
+            .parse()
+    }
+    let mut lines = s.lines().map(str::trim).filter(|x| !x.is_empty());
+    let first = lines.next().unwrap_or_default();
+    let mut res = parse_res(
+        first
+            .strip_prefix("Initial:")
+            .ok_or_else(|| bad_syntax(first))?,
+    )?;
+    let mut builder = Policy::new();
+    let mut deleted = false;
+    for line in lines {
+        if deleted {
+            // Only the length budget can come after the deletion
+            let max = line
+                .strip_prefix("Keep at most ")
+                .and_then(|x| x.strip_suffix(" entries"))
+                .ok_or_else(|| bad_syntax(line))?;
+            builder = builder.len_budget(parse_len(max)?);
+            continue;
+        }
+        let (days, action) = line
+            .strip_prefix("After ")
+            .and_then(|x| x.split_once(':'))
+            .ok_or_else(|| bad_syntax(line))?;
+        let days = days
+            .trim()
+            .strip_suffix("days")
+            .ok_or_else(|| bad_syntax(line))?;
+        builder = builder.keep_for_days(parse_days(days)?, res);
+        match action.trim() {
+            "delete" => deleted = true,
+            action => {
+                let action = action
+                    .strip_prefix("reduce to")
+                    .ok_or_else(|| bad_syntax(line))?;
+                res = parse_res(action)?;
+            }
+        }
+    }
+    if !deleted {
+        return Err(bad_syntax(s));
+    }
+    Ok(builder)
+}
+
 impl Policy {
     #[allow(clippy::new_ret_no_self)]
     pub fn new() -> PolicyBuilder {
@@ -50,29 +212,143 @@ impl Policy {
             .map(|(days, _)| *days)
             .chain([self.max_retention])
     }
+
+    /// Each resolution in the policy, from finest to coarsest, along with the
+    /// age at which data stops being stored at that resolution
+    pub(crate) fn tiers(&self) -> Vec<(Resolution, Days)> {
+        let mut until = self.max_retention;
+        let mut tiers = vec![];
+        for (days, res) in &self.compaction_rules {
+            tiers.push((*res, until));
+            until = *days;
+        }
+        tiers.push((self.max_res, until));
+        tiers.reverse();
+        tiers
+    }
+
+    /// An upper bound on the number of entries which a compactor using this
+    /// policy can hold.  Multiply by `size_of::<(Date, Time, T)>()` to get an
+    /// upper bound on its memory usage.
+    ///
+    /// ```
+    /// # use compactor::{policy::Policy, Resolution};
+    /// let policy = Policy::new()
+    ///     .keep_for_days(2, Resolution::Hour)
+    ///     .keep_for_days(10, Resolution::Day)
+    ///     .build()
+    ///     .unwrap();
+    /// assert_eq!(policy.max_len(), 2 * 24 + 8);
+    /// let policy = Policy::new()
+    ///     .keep_for_days(2, Resolution::Hour)
+    ///     .keep_for_days(10, Resolution::Day)
+    ///     .len_budget(20)
+    ///     .build()
+    ///     .unwrap();
+    /// assert_eq!(policy.max_len(), 20);
+    /// ```
+    pub fn max_len(&self) -> usize {
+        let tiers = self.tiers();
+        let mut total = 0;
+        let mut start = 0;
+        for (i, (res, end)) in tiers.iter().enumerate() {
+            // Periods longer than a day are only compacted (or discarded)
+            // once they're complete, so data can stay in this tier for a
+            // bit longer than the policy says
+            let next = tiers.get(i + 1).map_or(*res, |x| x.0);
+            let days = (end - start) as usize + period_days(next).1 - 1;
+            total += if *res >= Resolution::Day {
+                days * (Resolution::Day / *res) as usize
+            } else {
+                // The first and last periods may be partial
+                days.div_ceil(period_days(*res).0) + 1
+            };
+            start = *end;
+        }
+        self.len_budget.map_or(total, |n| n.min(total))
+    }
+
+    /// The resolution at which data `age` days old is stored, or `None` if
+    /// data that old is discarded
+    pub(crate) fn resolution_at(&self, age: Days) -> Option<Resolution> {
+        if age >= self.max_retention {
+            return None;
+        }
+        let rule = self.compaction_rules.iter().find(|(days, _)| age >= *days);
+        Some(rule.map_or(self.max_res, |(_, res)| *res))
+    }
+}
+
+/// The shortest and longest lengths of a period, in days
+fn period_days(res: Resolution) -> (usize, usize) {
+    match res {
+        Resolution::Year => (365, 366),
+        Resolution::Quarter => (90, 92),
+        Resolution::Month => (28, 31),
+        Resolution::Week => (7, 7),
+        _ => (1, 1),
+    }
 }
 
 #[derive(Default)]
-pub struct PolicyBuilder(Vec<(Days, Resolution)>);
+pub struct PolicyBuilder {
+    rules: Vec<(Days, Resolution)>,
+    len_budget: Option<usize>,
+}
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub enum PolicyError {
     ZeroRetention,
     PolicyAppliesForZeroDays,
     SomePoliciesDominateOthers,
+    /// Weeks don't line up with months, quarters, or years
+    WeeksAndMonths,
+    /// A string couldn't be parsed as a resolution
+    UnknownResolution(String),
+    /// A string couldn't be parsed as a policy.  This holds the part which
+    /// didn't make sense.
+    BadSyntax(String),
 }
 
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyError::ZeroRetention => f.write_str("The policy doesn't keep any data"),
+            PolicyError::PolicyAppliesForZeroDays => f.write_str("A rule applies for zero days"),
+            PolicyError::SomePoliciesDominateOthers => {
+                f.write_str("Some rules keep less data for longer than others")
+            }
+            PolicyError::WeeksAndMonths => {
+                f.write_str("Weeks can't be used with months, quarters, or years")
+            }
+            PolicyError::UnknownResolution(s) => write!(f, "Unknown resolution {s:?}"),
+            PolicyError::BadSyntax(s) => write!(f, "Bad policy syntax at {s:?}"),
+        }
+    }
+}
+
+impl std::error::Error for PolicyError {}
+
 impl PolicyBuilder {
     /// Allow this compactor to keep data at resolution `res` for up to
     /// `num_days` days
     pub fn keep_for_days(mut self, num_days: u16, res: Resolution) -> Self {
-        self.0.push((num_days, res));
+        self.rules.push((num_days, res));
+        self
+    }
+
+    /// Don't store more than `n` entries.  If a push takes the compactor over
+    /// the limit, the oldest data which isn't yet at the coarsest resolution is
+    /// compacted ahead of schedule.  If there's nothing left to compact, the
+    /// oldest data is discarded early instead.
+    pub fn len_budget(mut self, n: usize) -> Self {
+        self.len_budget = Some(n);
         self
     }
 
     pub fn build(self) -> Result<Policy, PolicyError> {
-        let mut raw_policy = self.0;
-        if raw_policy.is_empty() {
+        let mut raw_policy = self.rules;
+        if raw_policy.is_empty() || self.len_budget == Some(0) {
             return Err(PolicyError::ZeroRetention);
         }
         for (x, _) in &raw_policy {
@@ -85,6 +361,10 @@ impl PolicyBuilder {
         if !raw_policy.iter().is_sorted_by_key(|x| x.1) {
             return Err(PolicyError::SomePoliciesDominateOthers);
         }
+        let has_weeks = raw_policy.iter().any(|x| x.1 == Resolution::Week);
+        if has_weeks && raw_policy.iter().any(|x| x.1 < Resolution::Week) {
+            return Err(PolicyError::WeeksAndMonths);
+        }
         let max_res = raw_policy.last().unwrap().1;
         let max_retention = raw_policy.first().unwrap().0;
         let days = raw_policy.iter().map(|x| x.0).skip(1);
@@ -94,6 +374,7 @@ impl PolicyBuilder {
             compaction_rules: policy,
             max_res,
             max_retention,
+            len_budget: self.len_budget,
         })
     }
 }
@@ -134,6 +415,99 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_from_str() {
+        let policies = [
+            Policy::new()
+                .keep_for_days(1, Resolution::FiveSecond)
+                .keep_for_days(5, Resolution::Minute)
+                .keep_for_days(90, Resolution::Hour)
+                .keep_for_days(365, Resolution::Day),
+            Policy::new()
+                .keep_for_days(7, Resolution::AmPm)
+                .keep_for_days(90, Resolution::Week),
+            Policy::new()
+                .keep_for_days(2, Resolution::Hour)
+                .keep_for_days(730, Resolution::Quarter)
+                .len_budget(500),
+            Policy::new().keep_for_days(3, Resolution::Millisecond),
+        ];
+        for policy in policies {
+            let policy = policy.build().unwrap();
+            assert_eq!(policy.to_string().parse(), Ok(policy.clone()));
+            assert_eq!(format!("{policy:#}").parse(), Ok(policy.clone()));
+        }
+
+        let policy = Policy::new()
+            .keep_for_days(2, Resolution::Hour)
+            .keep_for_days(30, Resolution::Day)
+            .len_budget(100)
+            .build();
+        assert_eq!(" h:2d, day:30 , max:100".parse(), policy);
+        assert_eq!("H:2d,Day:30,MAX:100".parse(), policy);
+        assert_eq!("hour -> (2d) day -> (30d) delete (max 100)".parse(), policy);
+        assert_eq!("Hour -> (2d) Day -> (30d) Delete (Max 100)".parse(), policy);
+
+        let err = |s: &str| s.parse::<Policy>().unwrap_err();
+        let unknown = |s: &str| PolicyError::UnknownResolution(s.to_string());
+        let bad = |s: &str| PolicyError::BadSyntax(s.to_string());
+        assert_eq!(err(""), unknown(""));
+        assert_eq!(err("hour"), bad("hour"));
+        assert_eq!(err("fortnight:14d"), unknown("fortnight"));
+        assert_eq!(err("hour:two"), bad("two"));
+        assert_eq!(err("hour:2d,max:lots"), bad("lots"));
+        assert_eq!(err("hour → (2d) day"), bad("hour → (2d) day"));
+        assert_eq!(err("hour → (2d) delete → (3d) day"), bad("(3d) day"));
+        assert_eq!(err("hour → 2d delete"), bad("2d delete"));
+        assert_eq!(err("hour → (2d) delete (max)"), bad("delete (max)"));
+        assert_eq!(err("Initial: hour\nAfter 2 days: delete"), bad("hour"));
+        assert_eq!(
+            err("Initial: hour-resolution"),
+            bad("Initial: hour-resolution")
+        );
+        assert_eq!(
+            err("Initial: hour-resolution\nAfter 2: delete"),
+            bad("After 2: delete")
+        );
+        assert_eq!(
+            err("hour:5d,day:2d").to_string(),
+            "Some rules keep less data for longer than others"
+        );
+        assert_eq!(err("hour:two").to_string(), "Bad policy syntax at \"two\"");
+        // Errors from building are passed through
+        assert_eq!(
+            err("hour:5d,day:2d"),
+            PolicyError::SomePoliciesDominateOthers
+        );
+        assert_eq!(err("week:7d,month:90d"), PolicyError::WeeksAndMonths);
+        assert_eq!(err("hour:0d"), PolicyError::PolicyAppliesForZeroDays);
+    }
+
+    #[test]
+    fn test_resolution_at() {
+        let policy = Policy::new()
+            .keep_for_days(2, Resolution::Hour)
+            .keep_for_days(4, Resolution::AmPm)
+            .keep_for_days(6, Resolution::Day)
+            .build()
+            .unwrap();
+        let ress = (0..7)
+            .map(|age| policy.resolution_at(age))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ress,
+            vec![
+                Some(Resolution::Hour),
+                Some(Resolution::Hour),
+                Some(Resolution::AmPm),
+                Some(Resolution::AmPm),
+                Some(Resolution::Day),
+                Some(Resolution::Day),
+                None,
+            ]
+        );
+    }
+
     #[test]
     fn test_dominated_policies() {
         assert!(
@@ -159,6 +533,29 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_weeks_and_months() {
+        let policy = |res| {
+            PolicyBuilder::default()
+                .keep_for_days(7, Resolution::Day)
+                .keep_for_days(90, Resolution::Week)
+                .keep_for_days(365, res)
+                .build()
+        };
+        assert_eq!(policy(Resolution::Month), Err(PolicyError::WeeksAndMonths));
+        assert_eq!(policy(Resolution::Year), Err(PolicyError::WeeksAndMonths));
+        let policy = PolicyBuilder::default()
+            .keep_for_days(7, Resolution::Day)
+            .keep_for_days(90, Resolution::Month)
+            .keep_for_days(730, Resolution::Quarter)
+            .build()
+            .unwrap();
+        assert_eq!(
+            policy.to_string(),
+            "day →  (7d) month →  (90d) quarter →  (730d) delete"
+        );
+    }
+
     #[test]
     fn test_duplicate_policies() {
         let x = PolicyBuilder::default()
@@ -170,4 +567,38 @@ mod tests {
             .build();
         assert_eq!(x, y);
     }
+
+    #[test]
+    fn test_max_len() {
+        use crate::{Compactor, Date, Time};
+        let policies = [
+            Policy::new()
+                .keep_for_days(2, Resolution::Hour)
+                .keep_for_days(5, Resolution::AmPm)
+                .keep_for_days(30, Resolution::Day),
+            Policy::new()
+                .keep_for_days(3, Resolution::Day)
+                .keep_for_days(30, Resolution::Week),
+            Policy::new()
+                .keep_for_days(1, Resolution::Hour)
+                .keep_for_days(40, Resolution::Day)
+                .keep_for_days(200, Resolution::Month)
+                .keep_for_days(800, Resolution::Year),
+        ];
+        for policy in policies {
+            let policy = policy.build().unwrap();
+            let bound = policy.max_len();
+            let mut agg = Compactor::<Vec<()>>::from(policy.clone());
+            let mut max = 0;
+            let start = jiff::civil::date(2023, 1, 1);
+            for d in 0..1000 {
+                let date = Date::from_civil(start + jiff::Span::new().days(d));
+                for h in 0..24 {
+                    agg.push(date, Time::from_hour(h), vec![()]).unwrap();
+                    max = max.max(agg.len());
+                }
+            }
+            assert!(max <= bound, "{policy}: {max} > {bound}");
+        }
+    }
 }
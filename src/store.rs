@@ -0,0 +1,802 @@
+//! A [`Compactor`] which persists its data to disk
+//!
+//! The data lives in a directory, which looks like this:
+//!
+//! * `MANIFEST`: the policy, and the segments which make up the latest
+//!   checkpoint
+//! * `<date>.<generation>.seg`: a segment, holding all the entries for a
+//!   single date
+//! * `<generation>.log`: every push since the latest checkpoint
+//!
+//! Pushes are appended to the log.  Every so often, the store checkpoints:
+//! it writes out new segments for the dates whose data has changed, atomically
+//! replaces the manifest, and starts a new log.  Segments whose data hasn't
+//! changed are left alone, so compaction only rewrites the dates it affects.
+//!
+//! A store must always be opened with the same policy and lateness horizon.
+//! When it's opened, it loads the segments listed in the manifest and replays
+//! the log on top.  If the process crashed in the middle of writing a
+//! log record, the partial record is dropped.  If it crashed in the middle of a
+//! checkpoint, the old manifest is still in place, and any files written by the
+//! unfinished checkpoint are deleted.
+//!
+//! ```
+//! # use compactor::{Compactor, Date, Resolution, Time, store::Store};
+//! # let dir = std::env::temp_dir().join(format!("compactor-doctest-{}", std::process::id()));
+//! let compactor = Compactor::new()
+//!     .keep_for_days(7, Resolution::Hour)
+//!     .build()
+//!     .unwrap();
+//! let mut store = Store::open(&dir, compactor.clone()).unwrap();
+//! let date = Date { year: 2024, month: 3, day: 1 };
+//! store.push(date, Time::from_hour(9), vec![1_u32]).unwrap();
+//! drop(store);
+//!
+//! let store = Store::open(&dir, compactor).unwrap();
+//! assert_eq!(store.compactor().len(), 1);
+//! # std::fs::remove_dir_all(&dir).unwrap();
+//! ```
+
+use crate::{
+    Aggregate, Compactor, Date, Time,
+    codec::{Codec, check_header, fnv1a, invalid_data, take, write_checksum, write_header},
+    compactor::PushError,
+    data::{CompactedData, Dirty},
+    policy::Policy,
+};
+use core::fmt;
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+const MANIFEST: &str = "MANIFEST";
+const MANIFEST_MAGIC: &[u8; 4] = b"CPTM";
+const SEGMENT_MAGIC: &[u8; 4] = b"CPTS";
+const VERSION: u8 = 1;
+
+const PUSH: u8 = 0;
+const UPDATE_DATE: u8 = 1;
+
+#[derive(Debug)]
+pub enum StoreError {
+    /// Reading or writing the directory failed.  Data which doesn't make
+    /// sense, or which doesn't match the compactor's settings, is reported
+    /// with kind [`io::ErrorKind::InvalidData`].
+    Io(io::Error),
+    /// The compactor rejected a push, so it wasn't logged
+    Push(PushError),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Io(_) => f.write_str("Couldn't read or write the store"),
+            StoreError::Push(_) => f.write_str("The push was rejected"),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StoreError::Io(e) => Some(e),
+            StoreError::Push(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for StoreError {
+    fn from(e: io::Error) -> Self {
+        StoreError::Io(e)
+    }
+}
+
+impl From<PushError> for StoreError {
+    fn from(e: PushError) -> Self {
+        StoreError::Push(e)
+    }
+}
+
+/// A [`Compactor`] backed by a directory on disk
+///
+/// See the [module-level documentation](self) for details.
+pub struct Store<T> {
+    dir: PathBuf,
+    compactor: Compactor<T>,
+    generation: u64,
+    /// The generation of each date's segment
+    segments: BTreeMap<Date, u64>,
+    /// The dates whose segments need rewriting at the next checkpoint
+    dirty: Dirty,
+    log: File,
+    /// The length of the log, in bytes
+    log_len: u64,
+    log_records: usize,
+    checkpoint_every: usize,
+}
+
+impl<T: Aggregate + Codec> Store<T> {
+    /// Open the store in `dir`, creating it if it doesn't exist.
+    ///
+    /// The policy and other settings are taken from `compactor`.  If the store
+    /// already exists, they must match the ones it was created with, or this
+    /// returns an error with kind `InvalidData`.  Any data `compactor` already
+    /// contains is replaced by the data from the store.
+    pub fn open(dir: impl AsRef<Path>, mut compactor: Compactor<T>) -> Result<Self, StoreError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let manifest = Manifest {
+            policy: compactor.policy().clone(),
+            late_horizon: compactor.late_horizon,
+            date: None,
+            generation: 0,
+            segments: BTreeMap::new(),
+        };
+        let Manifest {
+            date,
+            generation,
+            segments,
+            ..
+        } = match fs::read(dir.join(MANIFEST)) {
+            Ok(bytes) => {
+                let existing = decode_manifest(&bytes)?;
+                if existing.policy != manifest.policy
+                    || existing.late_horizon != manifest.late_horizon
+                {
+                    return Err(invalid_data("The store has a different policy").into());
+                }
+                existing
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                write_atomic(&dir.join(MANIFEST), &encode_manifest(&manifest))?;
+                sync_dir(&dir)?;
+                manifest
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut entries = vec![];
+        for (date, generation) in &segments {
+            let bytes = fs::read(dir.join(segment_name(*date, *generation)))?;
+            decode_segment(*date, &bytes, &mut entries)?;
+        }
+        compactor.data = CompactedData(entries);
+        compactor.data.check(compactor.policy().max_res)?;
+        compactor.date = date;
+
+        // Replay the log
+        let log_path = dir.join(log_name(generation));
+        let bytes = match fs::read(&log_path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e.into()),
+        };
+        let mut buf = &bytes[..];
+        let mut log_records = 0;
+        let mut dirty = Dirty::tracking();
+        while let Some(mut payload) = read_record(&mut buf) {
+            let date = Date::decode(&mut payload)?;
+            match u8::decode(&mut payload)? {
+                PUSH => {
+                    let time = Time::decode(&mut payload)?;
+                    let x = T::decode(&mut payload)?;
+                    // Only successful pushes are logged, so this should
+                    // succeed too
+                    compactor
+                        .push_tracking(date, time, x, &mut dirty)
+                        .map_err(|_| invalid_data("Log contains a rejected push"))?;
+                }
+                UPDATE_DATE => compactor.update_date_tracking(date, &mut dirty),
+                _ => return Err(invalid_data("Bad log record").into()),
+            }
+            log_records += 1;
+        }
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log_path)?;
+        // Drop anything after the last complete record
+        let log_len = (bytes.len() - buf.len()) as u64;
+        log.set_len(log_len)?;
+
+        let store = Store {
+            dir,
+            compactor,
+            generation,
+            segments,
+            dirty,
+            log,
+            log_len,
+            log_records,
+            checkpoint_every: 10_000,
+        };
+        store.remove_stale_files()?;
+        Ok(store)
+    }
+
+    /// Checkpoint automatically once the log contains this many records.  The
+    /// default is 10,000.
+    pub fn checkpoint_every(mut self, records: usize) -> Self {
+        self.checkpoint_every = records;
+        self
+    }
+
+    /// See [`Compactor::push`].  The value is written to the log before this
+    /// returns, but it isn't necessarily on disk yet: see [`Store::sync`].
+    pub fn push(
+        &mut self,
+        date: impl Into<Date>,
+        time: impl Into<Time>,
+        x: T,
+    ) -> Result<(), StoreError> {
+        let date = date.into();
+        let time = time.into();
+        let mut payload = vec![];
+        date.encode(&mut payload);
+        PUSH.encode(&mut payload);
+        time.encode(&mut payload);
+        x.encode(&mut payload);
+        // Write the record first, so the compactor is never ahead of the log
+        let log_len = self.log_len;
+        self.append(&payload)?;
+        if let Err(e) = self.compactor.push_tracking(date, time, x, &mut self.dirty) {
+            // Only successful pushes are logged
+            self.log.set_len(log_len)?;
+            self.log_len = log_len;
+            return Err(e.into());
+        }
+        self.appended()
+    }
+
+    /// See [`Compactor::update_date`]
+    pub fn update_date(&mut self, date: impl Into<Date>) -> Result<(), StoreError> {
+        let date = date.into();
+        let mut payload = vec![];
+        date.encode(&mut payload);
+        UPDATE_DATE.encode(&mut payload);
+        self.append(&payload)?;
+        self.compactor.update_date_tracking(date, &mut self.dirty);
+        self.appended()
+    }
+
+    fn append(&mut self, payload: &[u8]) -> io::Result<()> {
+        let mut record = Vec::with_capacity(payload.len() + 8);
+        (payload.len() as u32).encode(&mut record);
+        (fnv1a(payload) as u32).encode(&mut record);
+        record.extend_from_slice(payload);
+        self.log.write_all(&record)?;
+        self.log_len += record.len() as u64;
+        Ok(())
+    }
+
+    /// Call this once an appended record has been applied to the compactor
+    fn appended(&mut self) -> Result<(), StoreError> {
+        self.log_records += 1;
+        if self.log_records >= self.checkpoint_every {
+            self.checkpoint()?;
+        }
+        Ok(())
+    }
+
+    /// Write out the segments for the dates which have changed since the last
+    /// checkpoint, and start a new log
+    pub fn checkpoint(&mut self) -> Result<(), StoreError> {
+        let generation = self.generation + 1;
+        let mut segments = self.segments.clone();
+        let data = &self.compactor.data.0;
+        for date in self.dirty.dates() {
+            let start = data.partition_point(|x| x.0 < date);
+            let end = data.partition_point(|x| x.0 <= date);
+            if start == end {
+                // Everything on this date was discarded or compacted away
+                segments.remove(&date);
+                continue;
+            }
+            let bytes = encode_segment(date, &data[start..end]);
+            write_atomic(&self.dir.join(segment_name(date, generation)), &bytes)?;
+            segments.insert(date, generation);
+        }
+        let manifest = Manifest {
+            policy: self.compactor.policy().clone(),
+            late_horizon: self.compactor.late_horizon,
+            date: self.compactor.date,
+            generation,
+            segments,
+        };
+        write_atomic(&self.dir.join(MANIFEST), &encode_manifest(&manifest))?;
+        sync_dir(&self.dir)?;
+
+        // The checkpoint is complete.  Now we can tidy up.
+        self.log = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(self.dir.join(log_name(generation)))?;
+        self.log_len = 0;
+        self.log_records = 0;
+        self.generation = generation;
+        self.segments = manifest.segments;
+        self.dirty.clear();
+        Ok(self.remove_stale_files()?)
+    }
+}
+
+impl<T> Store<T> {
+    pub fn compactor(&self) -> &Compactor<T> {
+        &self.compactor
+    }
+
+    /// Flush the log to disk
+    pub fn sync(&self) -> Result<(), StoreError> {
+        Ok(self.log.sync_data()?)
+    }
+
+    /// Remove logs and segments which aren't part of the current checkpoint
+    fn remove_stale_files(&self) -> io::Result<()> {
+        let log = log_name(self.generation);
+        for entry in fs::read_dir(&self.dir)? {
+            let name = entry?.file_name();
+            let Some(name) = name.to_str() else { continue };
+            let ours = is_log_name(name)
+                || is_segment_name(name, "seg")
+                || is_segment_name(name, "tmp")
+                || name == "MANIFEST.tmp";
+            let live = name == log
+                || self
+                    .segments
+                    .iter()
+                    .any(|(date, generation)| name == segment_name(*date, *generation));
+            if ours && !live {
+                fs::remove_file(self.dir.join(name))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn segment_name(date: Date, generation: u64) -> String {
+    format!("{date}.{generation}.seg")
+}
+
+fn log_name(generation: u64) -> String {
+    format!("{generation}.log")
+}
+
+/// Whether `name` looks like one of our segments (or a temporary file which
+/// was going to become one)
+fn is_segment_name(name: &str, extension: &str) -> bool {
+    let parse = || {
+        let (date, generation) = name
+            .strip_suffix(extension)?
+            .strip_suffix('.')?
+            .rsplit_once('.')?;
+        Some((
+            Date::from_civil(date.parse().ok()?),
+            generation.parse::<u64>().ok()?,
+        ))
+    };
+    parse().is_some_and(|(date, generation)| name == format!("{date}.{generation}.{extension}"))
+}
+
+fn is_log_name(name: &str) -> bool {
+    name.strip_suffix(".log")
+        .and_then(|x| x.parse::<u64>().ok())
+        .is_some_and(|generation| name == log_name(generation))
+}
+
+fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    fs::rename(tmp, path)
+}
+
+/// Make sure that renames in `dir` are on disk.  Windows doesn't let you open
+/// a directory, so there we have to trust the filesystem.
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+/// Split a complete record off the front of the log.  Returns `None` if the
+/// log ends here, or if the rest of it is damaged.
+fn read_record<'a>(buf: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let mut rest = *buf;
+    let len = u32::decode(&mut rest).ok()?;
+    let checksum = u32::decode(&mut rest).ok()?;
+    let payload = take(&mut rest, len as usize).ok()?;
+    if fnv1a(payload) as u32 != checksum {
+        return None;
+    }
+    *buf = rest;
+    Some(payload)
+}
+
+struct Manifest {
+    policy: Policy,
+    late_horizon: Option<Duration>,
+    /// See `Compactor::date`
+    date: Option<Date>,
+    generation: u64,
+    segments: BTreeMap<Date, u64>,
+}
+
+fn encode_manifest(manifest: &Manifest) -> Vec<u8> {
+    let mut buf = vec![];
+    write_header(&mut buf, MANIFEST_MAGIC, VERSION);
+    manifest.policy.encode(&mut buf);
+    manifest.late_horizon.encode(&mut buf);
+    manifest.date.encode(&mut buf);
+    manifest.generation.encode(&mut buf);
+    (manifest.segments.len() as u32).encode(&mut buf);
+    for (date, generation) in &manifest.segments {
+        date.encode(&mut buf);
+        generation.encode(&mut buf);
+    }
+    write_checksum(&mut buf);
+    buf
+}
+
+fn decode_manifest(bytes: &[u8]) -> io::Result<Manifest> {
+    let mut buf = check_header(bytes, MANIFEST_MAGIC, VERSION)?;
+    let policy = Policy::decode(&mut buf)?;
+    let late_horizon = Codec::decode(&mut buf)?;
+    let date = Codec::decode(&mut buf)?;
+    let generation = u64::decode(&mut buf)?;
+    let mut segments = BTreeMap::new();
+    for _ in 0..u32::decode(&mut buf)? {
+        let date = Date::decode(&mut buf)?;
+        segments.insert(date, u64::decode(&mut buf)?);
+    }
+    Ok(Manifest {
+        policy,
+        late_horizon,
+        date,
+        generation,
+        segments,
+    })
+}
+
+fn encode_segment<T: Codec>(date: Date, entries: &[(Date, Time, T)]) -> Vec<u8> {
+    let mut buf = vec![];
+    write_header(&mut buf, SEGMENT_MAGIC, VERSION);
+    date.encode(&mut buf);
+    (entries.len() as u32).encode(&mut buf);
+    for (_, time, x) in entries {
+        time.encode(&mut buf);
+        x.encode(&mut buf);
+    }
+    write_checksum(&mut buf);
+    buf
+}
+
+fn decode_segment<T: Codec>(
+    date: Date,
+    bytes: &[u8],
+    entries: &mut Vec<(Date, Time, T)>,
+) -> io::Result<()> {
+    let mut buf = check_header(bytes, SEGMENT_MAGIC, VERSION)?;
+    if Date::decode(&mut buf)? != date {
+        return Err(invalid_data("Segment has the wrong date"));
+    }
+    for _ in 0..u32::decode(&mut buf)? {
+        let time = Time::decode(&mut buf)?;
+        entries.push((date, time, T::decode(&mut buf)?));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Resolution;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("compactor-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn compactor() -> Compactor<Vec<u32>> {
+        Compactor::new()
+            .keep_for_days(2, Resolution::Hour)
+            .keep_for_days(10, Resolution::Day)
+            .build()
+            .unwrap()
+    }
+
+    fn date(day: i8) -> Date {
+        Date {
+            year: 2024,
+            month: 3,
+            day,
+        }
+    }
+
+    fn push_day(store: &mut Store<Vec<u32>>, day: i8) {
+        for h in [9, 10, 11] {
+            let x = day as u32 * 100 + h as u32;
+            store.push(date(day), Time::from_hour(h), vec![x]).unwrap();
+        }
+    }
+
+    fn is_invalid_data(e: &StoreError) -> bool {
+        matches!(e, StoreError::Io(e) if e.kind() == io::ErrorKind::InvalidData)
+    }
+
+    fn files(dir: &Path) -> Vec<String> {
+        let mut names = fs::read_dir(dir)
+            .unwrap()
+            .map(|x| x.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_replay() {
+        let dir = temp_dir("replay");
+        let mut store = Store::open(&dir, compactor()).unwrap();
+        for day in 1..=4 {
+            push_day(&mut store, day);
+        }
+        store.update_date(date(5)).unwrap();
+        let expected = store.compactor().clone();
+        drop(store);
+        let mut store = Store::open(&dir, compactor()).unwrap();
+        assert_eq!(store.compactor(), &expected);
+        // The current date survives a checkpoint too
+        store.checkpoint().unwrap();
+        drop(store);
+        let store = Store::open(&dir, compactor()).unwrap();
+        assert_eq!(store.compactor(), &expected);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let dir = temp_dir("checkpoint");
+        let mut store = Store::open(&dir, compactor()).unwrap();
+        for day in 1..=3 {
+            push_day(&mut store, day);
+        }
+        store.checkpoint().unwrap();
+        assert_eq!(
+            files(&dir),
+            [
+                "1.log",
+                "2024-03-01.1.seg",
+                "2024-03-02.1.seg",
+                "2024-03-03.1.seg",
+                "MANIFEST"
+            ]
+        );
+        // Compacts the 2nd
+        push_day(&mut store, 4);
+        store.checkpoint().unwrap();
+        assert_eq!(
+            files(&dir),
+            [
+                "2.log",
+                "2024-03-01.1.seg",
+                "2024-03-02.2.seg",
+                "2024-03-03.1.seg",
+                "2024-03-04.2.seg",
+                "MANIFEST"
+            ]
+        );
+        push_day(&mut store, 5);
+        let expected = store.compactor().clone();
+        drop(store);
+        let store = Store::open(&dir, compactor()).unwrap();
+        assert_eq!(store.compactor(), &expected);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_dirty() {
+        use crate::aggregate::Count;
+        let dir = temp_dir("dirty");
+        let compactor = Compactor::<Count>::new()
+            .keep_for_days(2, Resolution::Hour)
+            .keep_for_days(10, Resolution::Day)
+            .accept_late(Duration::from_secs(3 * 24 * 60 * 60))
+            .build()
+            .unwrap();
+        let mut store = Store::open(&dir, compactor.clone()).unwrap();
+        for day in 1..=3 {
+            store.push(date(day), Time::from_hour(9), Count(1)).unwrap();
+        }
+        store.checkpoint().unwrap();
+        // Only the count changes, not the shape of the data
+        store.push(date(2), Time::from_hour(9), Count(1)).unwrap();
+        store.checkpoint().unwrap();
+        assert_eq!(
+            files(&dir),
+            [
+                "2.log",
+                "2024-03-01.1.seg",
+                "2024-03-02.2.seg",
+                "2024-03-03.1.seg",
+                "MANIFEST"
+            ]
+        );
+        let expected = store.compactor().clone();
+        drop(store);
+        let mut store = Store::open(&dir, compactor.clone()).unwrap();
+        assert_eq!(store.compactor(), &expected);
+
+        // Discarded dates lose their segments, and compacted ones are
+        // rewritten
+        store.update_date(date(12)).unwrap();
+        store.checkpoint().unwrap();
+        assert_eq!(files(&dir), ["2024-03-03.3.seg", "3.log", "MANIFEST"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_checkpoint_every() {
+        let dir = temp_dir("checkpoint-every");
+        let mut store = Store::open(&dir, compactor()).unwrap().checkpoint_every(4);
+        for day in 1..=3 {
+            push_day(&mut store, day);
+        }
+        assert_eq!(store.generation, 2);
+        assert_eq!(store.log_records, 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_crash() {
+        let dir = temp_dir("crash");
+        let mut store = Store::open(&dir, compactor()).unwrap();
+        push_day(&mut store, 1);
+        store.checkpoint().unwrap();
+        push_day(&mut store, 2);
+        let expected = store.compactor().clone();
+        drop(store);
+
+        // A torn write at the end of the log
+        let mut log = OpenOptions::new()
+            .append(true)
+            .open(dir.join("1.log"))
+            .unwrap();
+        log.write_all(&[20, 0, 0, 0, 1, 2]).unwrap();
+        // An unfinished checkpoint
+        fs::write(dir.join("2024-03-02.2.seg"), b"garbage").unwrap();
+        fs::write(dir.join("MANIFEST.tmp"), b"garbage").unwrap();
+
+        let mut store = Store::open(&dir, compactor()).unwrap();
+        assert_eq!(store.compactor(), &expected);
+        assert_eq!(files(&dir), ["1.log", "2024-03-01.1.seg", "MANIFEST"]);
+        // The log is usable again
+        push_day(&mut store, 3);
+        let expected = store.compactor().clone();
+        drop(store);
+        let store = Store::open(&dir, compactor()).unwrap();
+        assert_eq!(store.compactor(), &expected);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_segment() {
+        let dir = temp_dir("corrupt");
+        let mut store = Store::open(&dir, compactor()).unwrap();
+        push_day(&mut store, 1);
+        store.checkpoint().unwrap();
+        drop(store);
+        let path = dir.join("2024-03-01.1.seg");
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[10] ^= 1;
+        fs::write(&path, bytes).unwrap();
+        let e = Store::open(&dir, compactor()).err().unwrap();
+        assert!(is_invalid_data(&e));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rejected_push() {
+        let dir = temp_dir("rejected");
+        let mut store = Store::open(&dir, compactor()).unwrap();
+        push_day(&mut store, 2);
+        let e = store.push(date(1), Time::from_hour(9), vec![0]).err();
+        assert!(matches!(e, Some(StoreError::Push(PushError::NonMonotonic))));
+        let e = e.unwrap();
+        assert_eq!(e.to_string(), "The push was rejected");
+        let source = std::error::Error::source(&e).unwrap();
+        assert_eq!(source.to_string(), PushError::NonMonotonic.to_string());
+        assert_eq!(store.log_records, 3);
+        // It was taken back out of the log
+        let expected = store.compactor().clone();
+        drop(store);
+        let store = Store::open(&dir, compactor()).unwrap();
+        assert_eq!(store.compactor(), &expected);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_policy_mismatch() {
+        let dir = temp_dir("mismatch");
+        let mut store = Store::open(&dir, compactor()).unwrap();
+        push_day(&mut store, 1);
+        drop(store);
+        let other = Compactor::<Vec<u32>>::new()
+            .keep_for_days(2, Resolution::Minute)
+            .build()
+            .unwrap();
+        let e = Store::open(&dir, other).err().unwrap();
+        assert!(is_invalid_data(&e));
+        fs::remove_dir_all(&dir).unwrap();
+
+        // The lateness horizon is part of the policy too
+        use crate::aggregate::Count;
+        let late = || {
+            Compactor::<Count>::new()
+                .keep_for_days(2, Resolution::Hour)
+                .accept_late(std::time::Duration::from_secs(3600))
+                .build()
+                .unwrap()
+        };
+        let mut store = Store::open(&dir, late()).unwrap();
+        store.push(date(1), Time::from_hour(9), Count(1)).unwrap();
+        store.push(date(1), Time::from_hour(8), Count(1)).unwrap();
+        drop(store);
+        let mut on_time = late();
+        on_time.late_horizon = None;
+        let e = Store::open(&dir, on_time).err().unwrap();
+        assert!(is_invalid_data(&e));
+        assert_eq!(Store::open(&dir, late()).unwrap().compactor().len(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_bad_segment() {
+        let dir = temp_dir("bad-segment");
+        drop(Store::open(&dir, compactor()).unwrap());
+        // Finer than the policy allows
+        let time = Time::from_hour(9).with_minute(30);
+        let bytes = encode_segment(date(1), &[(date(1), time, vec![1_u32])]);
+        fs::write(dir.join(segment_name(date(1), 1)), &bytes).unwrap();
+        let manifest = Manifest {
+            policy: compactor().policy().clone(),
+            late_horizon: None,
+            date: None,
+            generation: 1,
+            segments: BTreeMap::from([(date(1), 1)]),
+        };
+        fs::write(dir.join(MANIFEST), encode_manifest(&manifest)).unwrap();
+        let e = Store::open(&dir, compactor()).err().unwrap();
+        assert!(is_invalid_data(&e));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_other_files() {
+        let dir = temp_dir("other-files");
+        fs::create_dir_all(&dir).unwrap();
+        let others = ["README.tmp", "notes.log", "old.seg", "2024-03-01.seg"];
+        for name in others {
+            fs::write(dir.join(name), b"not ours").unwrap();
+        }
+        let mut store = Store::open(&dir, compactor()).unwrap();
+        push_day(&mut store, 1);
+        store.checkpoint().unwrap();
+        for name in others {
+            assert!(dir.join(name).exists(), "{name}");
+        }
+        assert!(!dir.join("0.log").exists());
+        assert!(is_segment_name("2024-03-01.2.tmp", "tmp"));
+        assert!(!is_segment_name("2024-03-01.2.seg", "tmp"));
+        assert!(is_log_name("12.log"));
+        assert!(!is_log_name("012.log"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,203 @@
+use crate::{
+    Aggregate, Compactor, Date, Time, UnixTime, aggregate::FromSample, compactor::PushError,
+    data::Dirty,
+};
+use jiff::{Timestamp, tz::TimeZone};
+
+/// A [`Compactor`] which is fed with instants, and buckets them by the local
+/// time in some time zone
+///
+/// Data is bucketed by wall-clock time, so a bucket like "09:00--10:00" means
+/// the same thing on every day, and policies count _calendar_ days in the time
+/// zone.  This works fine except on days when the clocks change:
+///
+/// * On a 23-hour day, there are no values with times inside the skipped hour.
+///   Those bit patterns of [`Time`] never occur, so those buckets are simply
+///   absent.  The remaining buckets are the usual width.
+/// * On a 25-hour day, the times inside the repeated hour occur twice, and map
+///   to the same bit patterns of [`Time`].  Values from the second pass are
+///   merged into the buckets from the first pass.  This means that the buckets
+///   in the repeated hour cover twice as much real time as usual.
+///
+/// In both cases the `Day` bucket covers the whole calendar day, even though
+/// its real width isn't `Resolution::Day.width()`.
+///
+/// ```
+/// # use compactor::{Compactor, Resolution, ZonedCompactor};
+/// # use jiff::{Timestamp, tz::TimeZone};
+/// let tz = TimeZone::posix("EST5EDT,M3.2.0,M11.1.0").unwrap();
+/// let compactor = Compactor::new()
+///     .keep_for_days(7, Resolution::Hour)
+///     .build()
+///     .unwrap();
+/// let mut compactor = ZonedCompactor::new(tz, compactor);
+/// // The clocks go back at 02:00 EDT, so 01:30 happens twice
+/// let ts: Timestamp = "2024-11-03T05:30:00Z".parse().unwrap();
+/// compactor.push(ts, vec![1]).unwrap();
+/// let ts: Timestamp = "2024-11-03T06:30:00Z".parse().unwrap();
+/// compactor.push(ts, vec![2]).unwrap();
+/// let (_, time, xs) = compactor.compactor().iter().next().unwrap();
+/// assert_eq!(time.to_string(), "01:00");
+/// assert_eq!(xs, &[1, 2]);
+/// ```
+#[derive(Clone, Debug)]
+pub struct ZonedCompactor<T> {
+    compactor: Compactor<T>,
+    tz: TimeZone,
+    latest: Option<Timestamp>,
+}
+
+impl<T> ZonedCompactor<T> {
+    pub fn new(tz: TimeZone, compactor: Compactor<T>) -> Self {
+        ZonedCompactor {
+            compactor,
+            tz,
+            latest: None,
+        }
+    }
+
+    pub fn time_zone(&self) -> &TimeZone {
+        &self.tz
+    }
+
+    pub fn compactor(&self) -> &Compactor<T> {
+        &self.compactor
+    }
+
+    pub fn into_compactor(self) -> Compactor<T> {
+        self.compactor
+    }
+
+    /// The local date and time of `ts`
+    fn local(&self, ts: Timestamp) -> (Date, Time) {
+        let dt = ts.to_zoned(self.tz.clone()).datetime();
+        (dt.date().into(), dt.time().into())
+    }
+}
+
+impl<T: Aggregate> ZonedCompactor<T> {
+    /// Accepts a [`Timestamp`], or anything which converts into one, like a
+    /// [`jiff::Zoned`].  The time zone of a `Zoned` is ignored in favour of
+    /// the compactor's own one.
+    pub fn push(&mut self, ts: impl Into<Timestamp>, x: T) -> Result<(), PushError> {
+        let ts = ts.into();
+        let (date, time) = self.local(ts);
+        let in_order = self.latest.is_none_or(|latest| ts >= latest);
+        let behind = self
+            .compactor
+            .iter()
+            .next_back()
+            .is_some_and(|(d, t, _)| (d, t.first_milli()) > (date, time.first_milli()));
+        if !in_order {
+            self.compactor
+                .push_late(date, time, x, &mut Dirty::default())?;
+        } else if behind {
+            // The clocks have gone back
+            self.compactor.insert(date, time, x, &mut Dirty::default());
+        } else {
+            self.compactor.push(date, time, x)?;
+        }
+        self.latest = self.latest.max(Some(ts));
+        Ok(())
+    }
+
+    /// Push a value to an aggregate which needs to know exactly when it was
+    /// sampled.  See [`Compactor::push_sample`].
+    pub fn push_sample<V>(&mut self, ts: impl Into<Timestamp>, x: V) -> Result<(), PushError>
+    where
+        T: FromSample<V>,
+    {
+        let ts = ts.into();
+        let t = UnixTime::from_millis(ts.as_millisecond());
+        self.push(ts, T::from_sample(t, x))
+    }
+
+    /// Update the current time without pushing any new data.  This can be used
+    /// to force compaction.
+    pub fn update_time(&mut self, ts: impl Into<Timestamp>) {
+        let (date, _) = self.local(ts.into());
+        self.compactor.update_date(date);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Resolution;
+
+    fn new_york() -> TimeZone {
+        TimeZone::posix("EST5EDT,M3.2.0,M11.1.0").unwrap()
+    }
+
+    fn hourly() -> ZonedCompactor<Vec<i64>> {
+        let compactor = Compactor::new()
+            .keep_for_days(3, Resolution::Hour)
+            .build()
+            .unwrap();
+        ZonedCompactor::new(new_york(), compactor)
+    }
+
+    fn date(year: i16, month: i8, day: i8) -> Date {
+        Date { year, month, day }
+    }
+
+    /// Push a value every 30 minutes for 24 hours, starting at `start`
+    fn push_day(agg: &mut ZonedCompactor<Vec<i64>>, start: &str) {
+        let start: Timestamp = start.parse().unwrap();
+        for i in 0..48 {
+            let ts = start + jiff::Span::new().minutes(30 * i);
+            agg.push(ts, vec![i]).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_spring_forward() {
+        let mut agg = hourly();
+        // Local midnight on 2024-03-10, a 23-hour day
+        push_day(&mut agg, "2024-03-10T05:00:00Z");
+        let day = agg
+            .compactor()
+            .iter()
+            .filter(|(d, _, _)| *d == date(2024, 3, 10))
+            .collect::<Vec<_>>();
+        assert_eq!(day.len(), 23);
+        assert!(day.iter().all(|(_, t, xs)| t.hour() != 2 && xs.len() == 2));
+        // The 48th value spills into the next day
+        let (d, t, xs) = agg.compactor().iter().next_back().unwrap();
+        assert_eq!(
+            (d, t, xs),
+            (date(2024, 3, 11), Time::from_hour(0), &vec![46, 47])
+        );
+    }
+
+    #[test]
+    fn test_fall_back() {
+        let mut agg = hourly();
+        // Local midnight on 2024-11-03, a 25-hour day
+        push_day(&mut agg, "2024-11-03T04:00:00Z");
+        let day = agg
+            .compactor()
+            .iter()
+            .filter(|(d, _, _)| *d == date(2024, 11, 3))
+            .collect::<Vec<_>>();
+        // 24 hours only takes us up to 23:00 local time
+        assert_eq!(day.len(), 23);
+        for (_, t, xs) in day {
+            match t.hour() {
+                // Both passes through the repeated hour
+                1 => assert_eq!(xs, &vec![2, 3, 4, 5]),
+                _ => assert_eq!(xs.len(), 2, "{t}"),
+            }
+        }
+        assert_eq!(agg.compactor().len(), 23);
+    }
+
+    #[test]
+    fn test_non_monotonic() {
+        let mut agg = hourly();
+        let ts: Timestamp = "2024-11-03T06:30:00Z".parse().unwrap();
+        agg.push(ts, vec![0]).unwrap();
+        let ts: Timestamp = "2024-11-03T05:30:00Z".parse().unwrap();
+        assert_eq!(agg.push(ts, vec![1]), Err(PushError::NonMonotonic));
+    }
+}